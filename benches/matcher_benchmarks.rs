@@ -26,8 +26,8 @@ fn benchmark_limit_order_matching(c: &mut Criterion) {
             );
 
             // Process the orders
-            matcher.proceed_record(buy_order);
-            matcher.proceed_record(sell_order);
+            matcher.proceed_record(buy_order).unwrap();
+            matcher.proceed_record(sell_order).unwrap();
         })
     });
 }
@@ -57,8 +57,8 @@ fn benchmark_fok_order_matching(c: &mut Criterion) {
             );
 
             // Process the orders
-            matcher.proceed_record(sell_order);
-            matcher.proceed_record(fok_buy_order);
+            matcher.proceed_record(sell_order).unwrap();
+            matcher.proceed_record(fok_buy_order).unwrap();
         })
     });
 }
@@ -88,8 +88,8 @@ fn benchmark_ioc_order_matching(c: &mut Criterion) {
             );
 
             // Process the orders
-            matcher.proceed_record(buy_order);
-            matcher.proceed_record(ioc_sell_order);
+            matcher.proceed_record(buy_order).unwrap();
+            matcher.proceed_record(ioc_sell_order).unwrap();
         })
     });
 }
@@ -117,7 +117,7 @@ fn benchmark_multiple_orders(c: &mut Criterion) {
 
             // Process all orders
             for order in orders {
-                matcher.proceed_record(order);
+                matcher.proceed_record(order).unwrap();
             }
         })
     });
@@ -155,8 +155,8 @@ fn benchmark_large_order_book(c: &mut Criterion) {
                     black_box(i + 100),
                 );
 
-                matcher.proceed_record(buy_order);
-                matcher.proceed_record(sell_order);
+                matcher.proceed_record(buy_order).unwrap();
+                matcher.proceed_record(sell_order).unwrap();
             }
         })
     });
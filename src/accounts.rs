@@ -0,0 +1,551 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MatchError, RejectReason};
+use crate::fee::FeeSchedule;
+use crate::order::{Order, Side};
+use crate::trade::Trade;
+use crate::Matcher;
+
+/// Holdings of one asset: `free` can back new orders, `locked` is held by
+/// resting orders until they trade or leave the book.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Balance {
+    pub free: u64,
+    pub locked: u64,
+}
+
+impl Balance {
+    pub fn total(&self) -> u64 {
+        self.free + self.locked
+    }
+
+    fn lock(&mut self, amount: u64) -> bool {
+        if self.free < amount {
+            return false;
+        }
+        self.free -= amount;
+        self.locked += amount;
+        true
+    }
+
+    fn unlock(&mut self, amount: u64) {
+        let amount = amount.min(self.locked);
+        self.locked -= amount;
+        self.free += amount;
+    }
+
+    /// Adds to the free amount, keeping the total within `u64`.
+    fn credit(&mut self, amount: u64) -> Option<()> {
+        self.free.checked_add(amount)?.checked_add(self.locked)?;
+        self.free += amount;
+        Some(())
+    }
+}
+
+/// Balances of one user. Base amounts are in quantity units of the
+/// instrument, quote amounts in notional units, i.e. price units times
+/// quantity units.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub base: Balance,
+    pub quote: Balance,
+}
+
+/// Per-user balances backing the orders of a matcher. A buy order locks its
+/// value at the limit price in quote, together with the highest fee it may
+/// pay; a sell order locks its quantity in base and pays its fees out of
+/// what it sells for. Every trade then moves the funds between the two
+/// users.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Accounts {
+    accounts: BTreeMap<u64, Account>,
+}
+
+impl Accounts {
+    pub fn new() -> Accounts {
+        Accounts::default()
+    }
+
+    /// The account of a user; users never seen have empty accounts.
+    pub fn get(&self, user_id: u64) -> Account {
+        self.accounts.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// All accounts that ever held funds, by user id.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Account)> {
+        self.accounts
+            .iter()
+            .map(|(&user_id, account)| (user_id, account))
+    }
+
+    pub fn deposit(&mut self, user_id: u64, base: u64, quote: u64) -> Result<(), MatchError> {
+        let mut account = self.get(user_id);
+        account
+            .base
+            .credit(base)
+            .and_then(|()| account.quote.credit(quote))
+            .ok_or(MatchError::BalanceOverflow(user_id))?;
+        self.accounts.insert(user_id, account);
+        Ok(())
+    }
+
+    /// Amount an order needs to have locked for its remaining quantity,
+    /// together with the asset it is taken from. A buy never trades above its
+    /// limit price, so the fee at that price, as maker or as taker, is the
+    /// most it can be charged.
+    fn required(o: &Order, qty: u64, fees: &FeeSchedule) -> (Side, u64) {
+        match o.side() {
+            Side::Buy => {
+                let fee = [true, false]
+                    .map(|is_maker| fees.fee(o.user_id(), is_maker, o.price(), qty))
+                    .into_iter()
+                    .max()
+                    .unwrap_or(0)
+                    .max(0);
+                let fee = u64::try_from(fee).unwrap_or(u64::MAX);
+                (Side::Buy, (o.price() * qty).saturating_add(fee))
+            }
+            Side::Sell => (Side::Sell, qty),
+        }
+    }
+
+    fn balance_mut(&mut self, user_id: u64, asset: Side) -> &mut Balance {
+        let account = self.accounts.entry(user_id).or_default();
+        match asset {
+            Side::Buy => &mut account.quote,
+            Side::Sell => &mut account.base,
+        }
+    }
+
+    /// Locks the funds for a new order. The order must have passed
+    /// validation, so its notional fits into `u64`.
+    fn lock(&mut self, o: &Order, fees: &FeeSchedule) -> Result<(), RejectReason> {
+        let (asset, amount) = Accounts::required(o, o.current_qty(), fees);
+        if !self.accounts.contains_key(&o.user_id())
+            || !self.balance_mut(o.user_id(), asset).lock(amount)
+        {
+            return Err(RejectReason::InsufficientBalance);
+        }
+        Ok(())
+    }
+
+    /// Locks more or gives back funds as an order changes from `current` to
+    /// `amended`. Nothing changes if the user cannot back the amendment.
+    fn relock(
+        &mut self,
+        current: &Order,
+        amended: &Order,
+        fees: &FeeSchedule,
+    ) -> Result<(), RejectReason> {
+        let (asset, held) = Accounts::required(current, current.current_qty(), fees);
+        let (_, needed) = Accounts::required(amended, amended.current_qty(), fees);
+        let balance = self.balance_mut(current.user_id(), asset);
+        if needed >= held {
+            if !balance.lock(needed - held) {
+                return Err(RejectReason::InsufficientBalance);
+            }
+        } else {
+            balance.unlock(held - needed);
+        }
+        Ok(())
+    }
+
+    /// Gives back what is still locked for an order leaving the engine.
+    fn unlock(&mut self, o: &Order, fees: &FeeSchedule) {
+        let (asset, amount) = Accounts::required(o, o.current_qty(), fees);
+        self.balance_mut(o.user_id(), asset).unlock(amount);
+    }
+
+    /// Moves `qty` at `price` from the seller to the buyer, before either
+    /// order is reduced by it. The buyer locked at its limit price and for
+    /// its highest fee, so what is not spent on the trade is freed again.
+    /// Every change is checked before any is made, so an error leaves both
+    /// accounts as they were.
+    fn settle(
+        &mut self,
+        buy: &Order,
+        sell: &Order,
+        qty: u64,
+        price: u64,
+        fees: &FeeSchedule,
+    ) -> Result<(), MatchError> {
+        let (_, before) = Accounts::required(buy, buy.current_qty(), fees);
+        let (_, after) = Accounts::required(buy, buy.current_qty().saturating_sub(qty), fees);
+        let held = before - after;
+        let Some(cost) = price.checked_mul(qty) else {
+            return Err(MatchError::NotionalOverflow);
+        };
+        let out_of_range = |o: &Order| MatchError::BalanceOverflow(o.user_id());
+
+        let mut buyer = self.get(buy.user_id());
+        buyer.quote.locked = buyer
+            .quote
+            .locked
+            .checked_sub(held)
+            .ok_or_else(|| out_of_range(buy))?;
+        held.checked_sub(cost)
+            .and_then(|improvement| buyer.quote.credit(improvement))
+            .and_then(|()| buyer.base.credit(qty))
+            .ok_or_else(|| out_of_range(buy))?;
+
+        // Both sides may belong to the same user
+        let mut seller = if sell.user_id() == buy.user_id() {
+            buyer
+        } else {
+            self.get(sell.user_id())
+        };
+        seller.base.locked = seller
+            .base
+            .locked
+            .checked_sub(qty)
+            .ok_or_else(|| out_of_range(sell))?;
+        seller
+            .quote
+            .credit(cost)
+            .ok_or_else(|| out_of_range(sell))?;
+
+        self.accounts.insert(buy.user_id(), buyer);
+        self.accounts.insert(sell.user_id(), seller);
+        Ok(())
+    }
+
+    /// Takes the fee of a trade from the free quote balance of a user, or
+    /// pays it out if it is a rebate. A buyer has just been given back what
+    /// it locked for the fee, a seller what it sold for.
+    fn charge(&mut self, user_id: u64, fee: i128) -> Result<(), MatchError> {
+        let quote = self.balance_mut(user_id, Side::Buy);
+        if fee >= 0 {
+            quote.free = u64::try_from(fee)
+                .ok()
+                .and_then(|fee| quote.free.checked_sub(fee))
+                .ok_or(MatchError::UnpaidFee(user_id))?;
+        } else {
+            u64::try_from(fee.unsigned_abs())
+                .ok()
+                .and_then(|rebate| quote.credit(rebate))
+                .ok_or(MatchError::BalanceOverflow(user_id))?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the accounts changed by a sweep, see [`settle`].
+    pub(crate) fn update(&mut self, changed: Accounts) {
+        self.accounts.extend(changed.accounts);
+    }
+}
+
+impl Matcher {
+    /// Enables balance checks: from now on orders are only accepted if their
+    /// user has enough free funds in `accounts`.
+    pub fn with_accounts(mut self, accounts: Accounts) -> Matcher {
+        self.accounts = Some(accounts);
+        self
+    }
+
+    /// The balances, or `None` if the matcher does not check them.
+    pub fn accounts(&self) -> Option<&Accounts> {
+        self.accounts.as_ref()
+    }
+
+    pub fn deposit(&mut self, user_id: u64, base: u64, quote: u64) -> Result<(), MatchError> {
+        self.accounts
+            .as_mut()
+            .ok_or(MatchError::AccountsDisabled)?
+            .deposit(user_id, base, quote)
+    }
+
+    pub(crate) fn lock_funds(&mut self, o: &Order) -> Result<(), RejectReason> {
+        match self.accounts.as_mut() {
+            Some(accounts) => accounts.lock(o, &self.fees),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the funds an order holds to what its amendment needs. The
+    /// amended order must have passed validation.
+    pub(crate) fn relock_funds(
+        &mut self,
+        current: &Order,
+        amended: &Order,
+    ) -> Result<(), RejectReason> {
+        match self.accounts.as_mut() {
+            Some(accounts) => accounts.relock(current, amended, &self.fees),
+            None => Ok(()),
+        }
+    }
+
+    /// Called for every order leaving the engine with quantity left.
+    pub(crate) fn unlock_funds(&mut self, o: &Order) {
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.unlock(o, &self.fees);
+        }
+    }
+}
+
+/// Settles a trade between `buy` and `sell`, then charges the fees of both
+/// sides in quote. This is done in `changed`, which holds the accounts a
+/// sweep of the book has changed so far; any other account is first copied
+/// from `accounts`, which stay untouched.
+pub(crate) fn settle(
+    changed: &mut Accounts,
+    accounts: &Accounts,
+    fees: &FeeSchedule,
+    buy: &Order,
+    sell: &Order,
+    fill: &Trade,
+) -> Result<(), MatchError> {
+    for user_id in [buy.user_id(), sell.user_id()] {
+        if let (Entry::Vacant(entry), Some(account)) = (
+            changed.accounts.entry(user_id),
+            accounts.accounts.get(&user_id),
+        ) {
+            entry.insert(*account);
+        }
+    }
+    changed.settle(buy, sell, fill.qty, fill.price, fees)?;
+    changed.charge(fill.taker_user_id, fill.taker_fee)?;
+    changed.charge(fill.maker_user_id, fill.maker_fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amend::Amend;
+    use crate::cancel::MassCancel;
+    use crate::fee::{FeeRate, FeeSchedule, FeeTier};
+    use crate::order::{IdGenerator, OrderType};
+    use uuid::Uuid;
+
+    fn funded() -> Matcher {
+        let mut matcher = Matcher::new().with_accounts(Accounts::new());
+        matcher.deposit(1, 0, 10_000).unwrap();
+        matcher.deposit(2, 50, 0).unwrap();
+        matcher
+    }
+
+    fn account(matcher: &Matcher, user_id: u64) -> Account {
+        matcher.accounts().unwrap().get(user_id)
+    }
+
+    #[test]
+    fn test_resting_orders_lock_funds() {
+        let mut matcher = funded();
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Buy, 100, 30, 1))
+            .unwrap();
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Sell, 110, 20, 2))
+            .unwrap();
+        assert_eq!(
+            account(&matcher, 1).quote,
+            Balance {
+                free: 7_000,
+                locked: 3_000
+            }
+        );
+        assert_eq!(
+            account(&matcher, 2).base,
+            Balance {
+                free: 30,
+                locked: 20
+            }
+        );
+
+        matcher.mass_cancel(&MassCancel::user(1));
+        assert_eq!(
+            account(&matcher, 1).quote,
+            Balance {
+                free: 10_000,
+                locked: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_trade_settles_at_execution_price() {
+        let mut matcher = funded();
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Sell, 95, 20, 2))
+            .unwrap();
+        // Locks 100 * 30, trades 20 at 95 and rests 10 at 100
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Buy, 100, 30, 1))
+            .unwrap();
+
+        assert_eq!(
+            account(&matcher, 1),
+            Account {
+                base: Balance {
+                    free: 20,
+                    locked: 0
+                },
+                quote: Balance {
+                    free: 10_000 - 1_900 - 1_000,
+                    locked: 1_000
+                },
+            }
+        );
+        assert_eq!(
+            account(&matcher, 2),
+            Account {
+                base: Balance {
+                    free: 30,
+                    locked: 0
+                },
+                quote: Balance {
+                    free: 1_900,
+                    locked: 0
+                },
+            }
+        );
+
+        // Trades 10 against the rest of the buy order, the other 5 are released
+        matcher
+            .proceed_record(Order::new(OrderType::Ioc, Side::Sell, 100, 15, 2))
+            .unwrap();
+        assert_eq!(
+            account(&matcher, 2).base,
+            Balance {
+                free: 20,
+                locked: 0
+            }
+        );
+        assert_eq!(account(&matcher, 1).quote.locked, 0);
+    }
+
+    #[test]
+    fn test_insufficient_balance_is_rejected() {
+        let mut matcher = funded();
+        assert_eq!(
+            matcher.proceed_record(Order::new(OrderType::Lim, Side::Buy, 100, 101, 1)),
+            Err(MatchError::Rejected(RejectReason::InsufficientBalance))
+        );
+        assert_eq!(
+            matcher.proceed_record(Order::new(OrderType::Fok, Side::Sell, 100, 1, 3)),
+            Err(MatchError::Rejected(RejectReason::InsufficientBalance))
+        );
+        assert_eq!(account(&matcher, 1).quote.locked, 0);
+
+        let mut without = Matcher::new();
+        assert_eq!(without.deposit(1, 1, 1), Err(MatchError::AccountsDisabled));
+    }
+
+    #[test]
+    fn test_fees_are_settled_in_quote() {
+        let fees = FeeSchedule::new(FeeTier {
+            maker: FeeRate::PerUnit(-1),
+            taker: FeeRate::Bps(10),
+        });
+        let mut matcher = funded().with_fees(fees);
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Sell, 100, 20, 2))
+            .unwrap();
+        // 10 bps of 1_000 for the taker, a rebate of 1 per unit for the maker
+        matcher
+            .proceed_record(Order::new(OrderType::Ioc, Side::Buy, 100, 10, 1))
+            .unwrap();
+        assert_eq!(account(&matcher, 1).quote.free, 10_000 - 1_000 - 1);
+        assert_eq!(account(&matcher, 2).quote.free, 1_000 + 10);
+
+        // A buy has to lock its fee along with the value of the order
+        matcher.deposit(3, 0, 1_000).unwrap();
+        assert_eq!(
+            matcher.proceed_record(Order::new(OrderType::Ioc, Side::Buy, 100, 10, 3)),
+            Err(MatchError::Rejected(RejectReason::InsufficientBalance))
+        );
+        assert_eq!(account(&matcher, 3).quote.free, 1_000);
+    }
+
+    #[test]
+    fn test_fully_locked_maker_pays_its_fee() {
+        let fees = FeeSchedule::new(FeeTier {
+            maker: FeeRate::Bps(10),
+            taker: FeeRate::Bps(20),
+        });
+        let mut matcher = funded().with_fees(fees);
+        matcher.deposit(3, 0, 2_004).unwrap();
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Buy, 100, 20, 3))
+            .unwrap();
+        assert_eq!(
+            account(&matcher, 3).quote,
+            Balance {
+                free: 0,
+                locked: 2_004
+            }
+        );
+
+        // Hit twice, the maker pays 10 bps of 1_000 each time from the 20 bps
+        // it locked for its highest fee and gets the rest back
+        for _ in 0..2 {
+            matcher
+                .proceed_record(Order::new(OrderType::Ioc, Side::Sell, 100, 10, 2))
+                .unwrap();
+        }
+        assert_eq!(
+            account(&matcher, 3),
+            Account {
+                base: Balance {
+                    free: 20,
+                    locked: 0
+                },
+                quote: Balance { free: 2, locked: 0 }
+            }
+        );
+        assert_eq!(matcher.positions().get(3).fees, 2);
+        // The seller pays 20 bps out of what it sold for
+        assert_eq!(account(&matcher, 2).quote.free, 2_000 - 4);
+    }
+
+    #[test]
+    fn test_failed_settlement_changes_nothing() {
+        let mut accounts = Accounts::new();
+        accounts.deposit(1, u64::MAX - 5, 1_000).unwrap();
+        accounts.deposit(2, 10, 0).unwrap();
+        let buy = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        let sell = Order::new(OrderType::Lim, Side::Sell, 100, 10, 2);
+        let fees = FeeSchedule::default();
+        accounts.lock(&buy, &fees).unwrap();
+        accounts.lock(&sell, &fees).unwrap();
+
+        let before = accounts.clone();
+        assert_eq!(
+            accounts.settle(&buy, &sell, 10, 100, &fees),
+            Err(MatchError::BalanceOverflow(1))
+        );
+        assert_eq!(accounts, before);
+    }
+
+    #[test]
+    fn test_amend_moves_locked_funds() {
+        let mut matcher = funded().with_ids(IdGenerator::sequential());
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Buy, 100, 30, 1))
+            .unwrap();
+        let amend = |qty| Amend {
+            order_id: Uuid::from_u128(1),
+            user_id: 1,
+            price: None,
+            qty: Some(qty),
+        };
+        assert_eq!(
+            matcher.amend(&amend(101)),
+            Err(MatchError::Rejected(RejectReason::InsufficientBalance))
+        );
+        assert_eq!(account(&matcher, 1).quote.locked, 3_000);
+
+        matcher.amend(&amend(100)).unwrap();
+        assert_eq!(account(&matcher, 1).quote.locked, 10_000);
+        matcher.amend(&amend(5)).unwrap();
+        assert_eq!(
+            account(&matcher, 1).quote,
+            Balance {
+                free: 9_500,
+                locked: 500
+            }
+        );
+    }
+}
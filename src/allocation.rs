@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+/// How an incoming quantity is shared between the resting orders of one price
+/// level.
+#[derive(Display, Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Allocation {
+    /// Strict time priority: the oldest order is filled first.
+    #[default]
+    Fifo,
+    /// Every order gets a share proportional to its size. Shares below
+    /// `min_qty` are dropped, and whatever is left after rounding goes to the
+    /// orders in time priority.
+    ProRata { min_qty: u64 },
+    /// The oldest order of the level is filled first, the rest of the
+    /// quantity is shared pro-rata between the remaining orders.
+    FifoTopProRata { min_qty: u64 },
+}
+
+impl Allocation {
+    /// Splits `qty` between resting orders with the given remaining
+    /// quantities, listed in time priority. The result holds the quantity to
+    /// fill for each of them and never exceeds either side.
+    pub fn allocate(&self, qty: u64, resting: &[u64]) -> Vec<u64> {
+        match *self {
+            Allocation::Fifo => fifo(qty, resting),
+            Allocation::ProRata { min_qty } => pro_rata(qty, resting, min_qty),
+            Allocation::FifoTopProRata { min_qty } => {
+                let Some((&top, rest)) = resting.split_first() else {
+                    return Vec::new();
+                };
+                let top_fill = qty.min(top);
+                let mut fills = vec![top_fill];
+                fills.extend(pro_rata(qty - top_fill, rest, min_qty));
+                fills
+            }
+        }
+    }
+}
+
+fn fifo(mut qty: u64, resting: &[u64]) -> Vec<u64> {
+    resting
+        .iter()
+        .map(|&available| {
+            let fill = qty.min(available);
+            qty -= fill;
+            fill
+        })
+        .collect()
+}
+
+fn pro_rata(qty: u64, resting: &[u64], min_qty: u64) -> Vec<u64> {
+    let total: u128 = resting.iter().map(|&q| u128::from(q)).sum();
+    if u128::from(qty) >= total {
+        return resting.to_vec();
+    }
+    let mut fills: Vec<u64> = resting
+        .iter()
+        .map(|&available| {
+            // Cannot exceed `available`, because `qty < total`
+            let share = (u128::from(qty) * u128::from(available) / total) as u64;
+            if share < min_qty {
+                0
+            } else {
+                share
+            }
+        })
+        .collect();
+    let allocated: u64 = fills.iter().sum();
+    let mut remainder = qty - allocated;
+    for (fill, &available) in fills.iter_mut().zip(resting) {
+        let extra = remainder.min(available - *fill);
+        *fill += extra;
+        remainder -= extra;
+    }
+    fills
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo() {
+        let fifo = Allocation::Fifo;
+        assert_eq!(fifo.allocate(25, &[10, 20, 30]), vec![10, 15, 0]);
+        assert_eq!(fifo.allocate(100, &[10, 20, 30]), vec![10, 20, 30]);
+        assert_eq!(fifo.allocate(5, &[]), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_pro_rata() {
+        // 25 * 10/60 = 4.17, 25 * 20/60 = 8.33, 25 * 30/60 = 12.5;
+        // the single unit left after rounding down goes to the oldest order
+        let pro_rata = Allocation::ProRata { min_qty: 0 };
+        assert_eq!(pro_rata.allocate(25, &[10, 20, 30]), vec![5, 8, 12]);
+
+        // 100 * 50/200 = 25, 100 * 100/200 = 50, 100 * 50/200 = 25
+        assert_eq!(pro_rata.allocate(100, &[50, 100, 50]), vec![25, 50, 25]);
+
+        // Enough quantity fills everyone
+        assert_eq!(pro_rata.allocate(60, &[10, 20, 30]), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_pro_rata_minimum_allocation() {
+        // 10 * 2/100 = 0.2, 10 * 8/100 = 0.8, 10 * 90/100 = 9;
+        // after rounding down 1 unit is left for the oldest order
+        let pro_rata = Allocation::ProRata { min_qty: 0 };
+        assert_eq!(pro_rata.allocate(10, &[2, 8, 90]), vec![1, 0, 9]);
+
+        // 30 * 10/100 = 3, 30 * 15/100 = 4.5, 30 * 75/100 = 22.5;
+        // the 3 and 4 shares are below the minimum of 5, so 30 - 22 = 8
+        // units go by time priority: 8 to the first order, capped at 10
+        let with_minimum = Allocation::ProRata { min_qty: 5 };
+        assert_eq!(with_minimum.allocate(30, &[10, 15, 75]), vec![8, 0, 22]);
+    }
+
+    #[test]
+    fn test_fifo_top_pro_rata() {
+        // The top order takes 10, the remaining 20 is split 20 * 20/50 = 8
+        // and 20 * 30/50 = 12
+        let hybrid = Allocation::FifoTopProRata { min_qty: 0 };
+        assert_eq!(hybrid.allocate(30, &[10, 20, 30]), vec![10, 8, 12]);
+
+        // Smaller than the top order
+        assert_eq!(hybrid.allocate(7, &[10, 20, 30]), vec![7, 0, 0]);
+
+        // 15 left after the top order: 15 * 20/50 = 6, 15 * 30/50 = 9
+        assert_eq!(hybrid.allocate(25, &[10, 20, 30]), vec![10, 6, 9]);
+    }
+
+    #[test]
+    fn test_allocation_never_exceeds_inputs() {
+        let policies = [
+            Allocation::Fifo,
+            Allocation::ProRata { min_qty: 0 },
+            Allocation::ProRata { min_qty: 3 },
+            Allocation::FifoTopProRata { min_qty: 2 },
+        ];
+        let resting = [7, 1, 13, 4, 9];
+        for policy in policies {
+            for qty in 0..40 {
+                let fills = policy.allocate(qty, &resting);
+                assert_eq!(fills.len(), resting.len());
+                assert_eq!(fills.iter().sum::<u64>(), qty.min(34));
+                for (fill, available) in fills.iter().zip(resting) {
+                    assert!(*fill <= available);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{MatchError, RejectReason};
+use crate::event::{Event, EventKind};
+use crate::Matcher;
+
+/// A change to a resting order. Reducing its quantity keeps its place in
+/// the queue, while a new price or a larger quantity sends it to the back
+/// of its level, and a new price may make it trade right away.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Amend {
+    pub order_id: Uuid,
+    /// Owner of the order; orders of other users cannot be amended.
+    pub user_id: u64,
+    /// New limit price, unchanged if `None`.
+    pub price: Option<u64>,
+    /// New remaining quantity, unchanged if `None`. What was already filled
+    /// stays filled.
+    pub qty: Option<u64>,
+}
+
+impl Matcher {
+    /// Cancels one resting order of a user, reporting it as Canceled.
+    pub fn cancel_order(&mut self, order_id: Uuid, user_id: u64) -> Result<(), MatchError> {
+        let Some(side) = self
+            .g
+            .find(order_id)
+            .filter(|o| o.user_id() == user_id)
+            .map(|o| o.side())
+        else {
+            return Err(MatchError::UnknownOrder(order_id));
+        };
+        for o in self.g.remove_where(side, |o| o.id() == order_id) {
+            self.unlock_funds(&o);
+            self.emit(Event::new(EventKind::Canceled, &o));
+        }
+        Ok(())
+    }
+
+    /// Applies an amendment, reporting the order as Amended. An amendment
+    /// the order would not pass validation with, or that needs more funds
+    /// than the user has, is rejected and leaves the order as it was.
+    pub fn amend(&mut self, amend: &Amend) -> Result<(), MatchError> {
+        let Some(current) = self
+            .g
+            .find(amend.order_id)
+            .filter(|o| o.user_id() == amend.user_id)
+            .cloned()
+        else {
+            return Err(MatchError::UnknownOrder(amend.order_id));
+        };
+        let price = amend.price.unwrap_or(current.price());
+        let qty = amend.qty.unwrap_or(current.current_qty());
+        let mut amended = current.clone();
+        let valid = if qty == 0 {
+            Err(RejectReason::ZeroQuantity)
+        } else {
+            amended.amend(price, qty).and_then(|()| amended.validate())
+        };
+        let funded = valid.and_then(|()| self.relock_funds(&current, &amended));
+        if let Err(reason) = funded {
+            self.emit(Event::rejected(&amended, reason));
+            return Err(MatchError::Rejected(reason));
+        }
+
+        self.emit(Event::new(EventKind::Amended, &amended));
+        if price == current.price() && qty <= current.current_qty() {
+            *self
+                .g
+                .find_mut(amend.order_id)
+                .expect("order was found above") = amended;
+            return Ok(());
+        }
+        self.g
+            .remove_where(current.side(), |o| o.id() == amend.order_id);
+        self.process_lim(amended)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{IdGenerator, Order, OrderType, Side};
+
+    fn matcher_with_bids() -> Matcher {
+        let mut matcher = Matcher::new().with_ids(IdGenerator::sequential());
+        let orders = [
+            Order::new(OrderType::Lim, Side::Buy, 100, 10, 1),
+            Order::new(OrderType::Lim, Side::Buy, 100, 10, 2),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+        matcher.drain_events();
+        matcher
+    }
+
+    fn bids(matcher: &Matcher) -> Vec<(u64, u64, u64)> {
+        matcher
+            .g
+            .orders(Side::Buy)
+            .map(|o| (o.user_id(), o.price(), o.current_qty()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let mut matcher = matcher_with_bids();
+        assert_eq!(
+            matcher.cancel_order(Uuid::from_u128(1), 2),
+            Err(MatchError::UnknownOrder(Uuid::from_u128(1)))
+        );
+        matcher.cancel_order(Uuid::from_u128(1), 1).unwrap();
+        let events: Vec<Event> = matcher.drain_events().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EventKind::Canceled);
+        assert_eq!(bids(&matcher), vec![(2, 100, 10)]);
+    }
+
+    #[test]
+    fn test_reduce_keeps_priority() {
+        let mut matcher = matcher_with_bids();
+        let reduce = Amend {
+            order_id: Uuid::from_u128(1),
+            user_id: 1,
+            price: None,
+            qty: Some(4),
+        };
+        matcher.amend(&reduce).unwrap();
+        assert_eq!(bids(&matcher), vec![(1, 100, 4), (2, 100, 10)]);
+
+        let event = matcher.drain_events().next().unwrap();
+        assert_eq!(event.kind, EventKind::Amended);
+        assert_eq!(event.order.initial_qty(), 4);
+    }
+
+    #[test]
+    fn test_increase_loses_priority() {
+        let mut matcher = matcher_with_bids();
+        let increase = Amend {
+            order_id: Uuid::from_u128(1),
+            user_id: 1,
+            price: None,
+            qty: Some(12),
+        };
+        matcher.amend(&increase).unwrap();
+        assert_eq!(bids(&matcher), vec![(2, 100, 10), (1, 100, 12)]);
+    }
+
+    #[test]
+    fn test_reprice_can_trade() {
+        let mut matcher = matcher_with_bids();
+        matcher
+            .proceed_record(Order::new(OrderType::Lim, Side::Sell, 101, 15, 3))
+            .unwrap();
+        matcher.drain_events();
+
+        let reprice = Amend {
+            order_id: Uuid::from_u128(2),
+            user_id: 2,
+            price: Some(101),
+            qty: None,
+        };
+        matcher.amend(&reprice).unwrap();
+        let kinds: Vec<EventKind> = matcher.drain_events().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Amended, EventKind::Executed]);
+        assert_eq!(bids(&matcher), vec![(1, 100, 10)]);
+        assert_eq!(
+            matcher.g.orders(Side::Sell).next().unwrap().current_qty(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_invalid_amend_is_rejected() {
+        let mut matcher = matcher_with_bids();
+        let zero = Amend {
+            order_id: Uuid::from_u128(1),
+            user_id: 1,
+            price: None,
+            qty: Some(0),
+        };
+        assert_eq!(
+            matcher.amend(&zero),
+            Err(MatchError::Rejected(RejectReason::ZeroQuantity))
+        );
+        assert_eq!(bids(&matcher), vec![(1, 100, 10), (2, 100, 10)]);
+    }
+
+    #[test]
+    fn test_amend_beyond_u64_is_rejected() {
+        let mut matcher = matcher_with_bids();
+        matcher
+            .proceed_record(Order::new(OrderType::Ioc, Side::Sell, 100, 4, 3))
+            .unwrap();
+        matcher.drain_events();
+
+        let huge = Amend {
+            order_id: Uuid::from_u128(1),
+            user_id: 1,
+            price: None,
+            qty: Some(u64::MAX),
+        };
+        assert_eq!(
+            matcher.amend(&huge),
+            Err(MatchError::Rejected(RejectReason::QuantityOverflow))
+        );
+        let event = matcher.drain_events().next().unwrap();
+        assert_eq!(event.kind, EventKind::Rejected);
+        assert_eq!(bids(&matcher), vec![(1, 100, 6), (2, 100, 10)]);
+        let order = matcher.g.orders(Side::Buy).next().unwrap();
+        assert_eq!(order.initial_qty(), 10);
+    }
+}
@@ -1,66 +1,176 @@
-use priority_queue::PriorityQueue;
-use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::order;
 
-#[derive(Eq, PartialEq)]
-struct PricePriority {
-    price: u64,
-    side: order::Side,
+/// Resting orders of one price, oldest first.
+pub type Level = VecDeque<order::Order>;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(try_from = "BookData", into = "BookData")]
+pub struct Book {
+    buy_levels: BTreeMap<u64, Level>,
+    sell_levels: BTreeMap<u64, Level>,
 }
 
-impl PartialOrd for PricePriority {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+/// Serialized form of a book: the resting orders of each side in priority
+/// order, so that pushing them back rebuilds the same levels.
+#[derive(Serialize, Deserialize)]
+struct BookData {
+    buy: Vec<order::Order>,
+    sell: Vec<order::Order>,
 }
 
-impl Ord for PricePriority {
-    fn cmp(&self, other: &Self) -> Ordering {
-        let res = self.price.cmp(&other.price);
-        if res == Ordering::Equal || self.side == order::Side::Buy {
-            return res;
+impl From<Book> for BookData {
+    fn from(book: Book) -> BookData {
+        BookData {
+            buy: book.orders(order::Side::Buy).cloned().collect(),
+            sell: book.orders(order::Side::Sell).cloned().collect(),
         }
-        return res.reverse();
     }
 }
 
-#[derive(Default)]
-pub struct Book {
-    buy_queue: PriorityQueue<order::Order, PricePriority>,
-    sell_queue: PriorityQueue<order::Order, PricePriority>,
+impl TryFrom<BookData> for Book {
+    type Error = String;
+
+    fn try_from(data: BookData) -> Result<Book, String> {
+        let mut book = Book::default();
+        for (side, orders) in [(order::Side::Buy, data.buy), (order::Side::Sell, data.sell)] {
+            for o in orders {
+                if o.side() != side {
+                    return Err(format!("{} order found among {} orders", o.side(), side));
+                }
+                if o.current_qty() == 0 || o.current_qty() > o.initial_qty() {
+                    return Err(format!(
+                        "resting order has invalid quantity {} of {}",
+                        o.current_qty(),
+                        o.initial_qty()
+                    ));
+                }
+                book.push(o);
+            }
+        }
+        Ok(book)
+    }
 }
 
 impl Book {
-    fn get_queue(&mut self, side: order::Side) -> &mut PriorityQueue<order::Order, PricePriority> {
+    fn get_levels(&mut self, side: order::Side) -> &mut BTreeMap<u64, Level> {
         match side {
-            order::Side::Buy => return &mut self.buy_queue,
-            order::Side::Sell => return &mut self.sell_queue,
+            order::Side::Buy => &mut self.buy_levels,
+            order::Side::Sell => &mut self.sell_levels,
         }
     }
 
-    pub fn pop(&mut self, side: order::Side) -> Option<order::Order> {
-        if let Some(res) = self.get_queue(side).pop() {
-            return Some(res.0);
+    /// Price levels of a side from the best price to the worst one.
+    pub fn levels(&self, side: order::Side) -> Box<dyn Iterator<Item = (u64, &Level)> + '_> {
+        match side {
+            order::Side::Buy => Box::new(self.buy_levels.iter().rev().map(|(p, l)| (*p, l))),
+            order::Side::Sell => Box::new(self.sell_levels.iter().map(|(p, l)| (*p, l))),
+        }
+    }
+
+    /// Resting orders of a side in priority order.
+    pub fn orders(&self, side: order::Side) -> impl Iterator<Item = &order::Order> + '_ {
+        self.levels(side).flat_map(|(_, level)| level.iter())
+    }
+
+    /// Price, total quantity and number of orders of each level of a side,
+    /// from the best price to the worst one.
+    pub fn depth(&self, side: order::Side) -> impl Iterator<Item = (u64, u64, usize)> + '_ {
+        self.levels(side).map(|(price, level)| {
+            let qty = level.iter().map(|o| o.current_qty()).sum();
+            (price, qty, level.len())
+        })
+    }
+
+    /// The level with the best price of a side.
+    fn best_level_mut(&mut self, side: order::Side) -> Option<(u64, &mut Level)> {
+        let levels = self.get_levels(side);
+        let entry = match side {
+            order::Side::Buy => levels.last_entry(),
+            order::Side::Sell => levels.first_entry(),
+        };
+        entry.map(|e| {
+            let price = *e.key();
+            (price, e.into_mut())
+        })
+    }
+
+    /// Puts `level` in place of the level of a side at `price`, or removes
+    /// that level if `level` is empty.
+    pub(crate) fn set_level(&mut self, side: order::Side, price: u64, level: Level) {
+        let levels = self.get_levels(side);
+        if level.is_empty() {
+            levels.remove(&price);
         } else {
-            return None;
+            levels.insert(price, level);
         }
     }
 
-    pub fn peek_mut(&mut self, side: order::Side) -> Option<&mut order::Order> {
-        if let Some(res) = self.get_queue(side).peek_mut() {
-            return Some(res.0);
+    /// Removes the orders of a side selected by `pred` and returns them in
+    /// priority order. The remaining orders keep their priority.
+    pub fn remove_where<F>(&mut self, side: order::Side, mut pred: F) -> Vec<order::Order>
+    where
+        F: FnMut(&order::Order) -> bool,
+    {
+        let levels = self.get_levels(side);
+        let mut removed = Vec::new();
+        let mut prices: Vec<u64> = levels.keys().copied().collect();
+        if side == order::Side::Buy {
+            prices.reverse();
+        }
+        for price in prices {
+            let Some(level) = levels.get_mut(&price) else {
+                continue;
+            };
+            let (selected, resting): (Level, Level) = level.drain(..).partition(|o| pred(o));
+            removed.extend(selected);
+            if resting.is_empty() {
+                levels.remove(&price);
+            } else {
+                *level = resting;
+            }
         }
-        return None;
+        removed
+    }
+
+    /// The resting order with the given id, found by a scan of both sides.
+    pub fn find(&self, id: Uuid) -> Option<&order::Order> {
+        self.orders(order::Side::Buy)
+            .chain(self.orders(order::Side::Sell))
+            .find(|o| o.id() == id)
+    }
+
+    pub fn find_mut(&mut self, id: Uuid) -> Option<&mut order::Order> {
+        self.buy_levels
+            .values_mut()
+            .chain(self.sell_levels.values_mut())
+            .flat_map(|level| level.iter_mut())
+            .find(|o| o.id() == id)
+    }
+
+    pub fn pop(&mut self, side: order::Side) -> Option<order::Order> {
+        let (price, level) = self.best_level_mut(side)?;
+        let res = level.pop_front();
+        if level.is_empty() {
+            self.get_levels(side).remove(&price);
+        }
+        res
+    }
+
+    pub fn peek_mut(&mut self, side: order::Side) -> Option<&mut order::Order> {
+        self.best_level_mut(side)
+            .and_then(|(_, level)| level.front_mut())
     }
 
     pub fn push(&mut self, o: order::Order) {
-        let side = o.side();
-        let pp = PricePriority {
-            price: o.price(),
-            side: side,
-        };
-        self.get_queue(side).push(o, pp);
+        self.get_levels(o.side())
+            .entry(o.price())
+            .or_default()
+            .push_back(o);
     }
 }
 
@@ -115,6 +225,47 @@ mod tests {
         assert_eq!(book.pop(Side::Sell).unwrap(), sell_order1);
     }
 
+    #[test]
+    fn test_book_time_priority() {
+        let mut book = Book::default();
+
+        let first = Order::new(OrderType::Lim, Side::Sell, 100, 10, 1);
+        let second = Order::new(OrderType::Lim, Side::Sell, 100, 20, 2);
+        let third = Order::new(OrderType::Lim, Side::Sell, 100, 30, 3);
+        book.push(first.clone());
+        book.push(second.clone());
+        book.push(third.clone());
+
+        // Orders of the same price leave the book in the order they came in
+        assert_eq!(book.pop(Side::Sell).unwrap(), first);
+        assert_eq!(book.pop(Side::Sell).unwrap(), second);
+        assert_eq!(book.pop(Side::Sell).unwrap(), third);
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut book = Book::default();
+        book.push(Order::new(OrderType::Lim, Side::Buy, 100, 10, 1));
+        book.push(Order::new(OrderType::Lim, Side::Buy, 102, 5, 2));
+        book.push(Order::new(OrderType::Lim, Side::Buy, 102, 7, 3));
+
+        let prices: Vec<u64> = book.levels(Side::Buy).map(|(price, _)| price).collect();
+        assert_eq!(prices, vec![102, 100]);
+        assert_eq!(book.peek_mut(Side::Buy).unwrap().user_id(), 2);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut book = Book::default();
+        book.push(Order::new(OrderType::Lim, Side::Sell, 105, 4, 1));
+        book.push(Order::new(OrderType::Lim, Side::Sell, 103, 5, 2));
+        book.push(Order::new(OrderType::Lim, Side::Sell, 103, 7, 3));
+
+        let depth: Vec<(u64, u64, usize)> = book.depth(Side::Sell).collect();
+        assert_eq!(depth, vec![(103, 12, 2), (105, 4, 1)]);
+        assert_eq!(book.depth(Side::Buy).next(), None);
+    }
+
     #[test]
     fn test_peek_mut() {
         let mut book = Book::default();
@@ -130,7 +281,7 @@ mod tests {
         assert_eq!(peeked.price(), 100);
 
         // Modify the order quantity
-        peeked.reduce_quantity(5);
+        peeked.reduce_quantity(5).unwrap();
 
         // Verify the change was applied
         let modified = book.pop(Side::Buy).unwrap();
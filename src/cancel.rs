@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use crate::event::{Event, EventKind};
+use crate::order::{Order, Side};
+use crate::Matcher;
+
+/// Selects the resting orders of one user to cancel at once, e.g. when the
+/// user disconnects or hits a risk limit.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct MassCancel {
+    pub user_id: u64,
+    /// Only orders of this side, both sides if `None`.
+    pub side: Option<Side>,
+    /// Only orders with at least this price.
+    pub min_price: Option<u64>,
+    /// Only orders with at most this price.
+    pub max_price: Option<u64>,
+}
+
+impl MassCancel {
+    /// Every resting order of the user.
+    pub fn user(user_id: u64) -> MassCancel {
+        MassCancel {
+            user_id,
+            side: None,
+            min_price: None,
+            max_price: None,
+        }
+    }
+
+    pub fn side(mut self, side: Side) -> MassCancel {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn price_range(mut self, min_price: Option<u64>, max_price: Option<u64>) -> MassCancel {
+        self.min_price = min_price;
+        self.max_price = max_price;
+        self
+    }
+
+    pub fn matches(&self, o: &Order) -> bool {
+        o.user_id() == self.user_id
+            && self.side.is_none_or(|side| o.side() == side)
+            && self.min_price.is_none_or(|min| o.price() >= min)
+            && self.max_price.is_none_or(|max| o.price() <= max)
+    }
+}
+
+/// What a mass cancel removed from the book.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct MassCancelSummary {
+    /// Number of canceled orders.
+    pub orders: usize,
+    /// Total remaining quantity of the canceled orders, which may not fit
+    /// into `u64`.
+    pub qty: u128,
+}
+
+impl Matcher {
+    /// Cancels every resting order selected by `filter`, reporting a
+    /// Canceled event for each of them in priority order, buy side first.
+    pub fn mass_cancel(&mut self, filter: &MassCancel) -> MassCancelSummary {
+        let mut summary = MassCancelSummary::default();
+        for side in [Side::Buy, Side::Sell] {
+            if filter.side.is_some_and(|s| s != side) {
+                continue;
+            }
+            for o in self.g.remove_where(side, |o| filter.matches(o)) {
+                self.unlock_funds(&o);
+                summary.orders += 1;
+                summary.qty += u128::from(o.current_qty());
+                self.emit(Event::new(EventKind::Canceled, &o));
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::OrderType;
+
+    fn book_with_orders() -> Matcher {
+        let mut matcher = Matcher::new();
+        for (side, price, qty, user_id) in [
+            (Side::Buy, 100, 10, 1),
+            (Side::Buy, 99, 5, 2),
+            (Side::Buy, 98, 7, 1),
+            (Side::Sell, 105, 3, 1),
+            (Side::Sell, 107, 4, 1),
+            (Side::Sell, 107, 6, 2),
+        ] {
+            let o = Order::new(OrderType::Lim, side, price, qty, user_id);
+            matcher.proceed_record(o).unwrap();
+        }
+        matcher.drain_events();
+        matcher
+    }
+
+    fn canceled(matcher: &mut Matcher) -> Vec<(Side, u64)> {
+        matcher
+            .drain_events()
+            .map(|e| {
+                assert_eq!(e.kind, EventKind::Canceled);
+                (e.order.side(), e.order.price())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cancel_all_of_user() {
+        let mut matcher = book_with_orders();
+        let summary = matcher.mass_cancel(&MassCancel::user(1));
+        assert_eq!(summary, MassCancelSummary { orders: 4, qty: 24 });
+        assert_eq!(
+            canceled(&mut matcher),
+            vec![
+                (Side::Buy, 100),
+                (Side::Buy, 98),
+                (Side::Sell, 105),
+                (Side::Sell, 107)
+            ]
+        );
+
+        // Orders of the other user are still there
+        matcher.shutdown();
+        assert_eq!(matcher.drain_events().count(), 2);
+    }
+
+    #[test]
+    fn test_cancel_by_side_and_price() {
+        let mut matcher = book_with_orders();
+        let summary = matcher.mass_cancel(&MassCancel::user(1).side(Side::Sell));
+        assert_eq!(summary, MassCancelSummary { orders: 2, qty: 7 });
+
+        let mut matcher = book_with_orders();
+        let filter = MassCancel::user(1).price_range(Some(99), Some(106));
+        assert_eq!(matcher.mass_cancel(&filter).orders, 2);
+        assert_eq!(
+            canceled(&mut matcher),
+            vec![(Side::Buy, 100), (Side::Sell, 105)]
+        );
+
+        assert_eq!(
+            matcher.mass_cancel(&MassCancel::user(3)),
+            MassCancelSummary::default()
+        );
+    }
+
+    #[test]
+    fn test_total_beyond_u64() {
+        let mut matcher = Matcher::new();
+        for _ in 0..3 {
+            let o = Order::new(OrderType::Lim, Side::Sell, 1, u64::MAX / 2, 1);
+            matcher.proceed_record(o).unwrap();
+        }
+        let summary = matcher.mass_cancel(&MassCancel::user(1));
+        assert_eq!(summary.qty, u128::from(u64::MAX / 2) * 3);
+    }
+}
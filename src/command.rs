@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::amend::Amend;
+use crate::cancel::MassCancel;
+use crate::error::MatchError;
+use crate::order::Order;
+use crate::Matcher;
+
+/// An input to the matcher that may change its state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    NewOrder(Order),
+    Cancel { order_id: Uuid, user_id: u64 },
+    Amend(Amend),
+    MassCancel(MassCancel),
+    OpenSession { session_id: u64, user_id: u64 },
+    CloseSession { session_id: u64 },
+    Deposit { user_id: u64, base: u64, quote: u64 },
+    Shutdown,
+}
+
+impl Matcher {
+    pub fn execute(&mut self, command: Command) -> Result<(), MatchError> {
+        match command {
+            Command::NewOrder(o) => self.proceed_record(o),
+            Command::Cancel { order_id, user_id } => self.cancel_order(order_id, user_id),
+            Command::Amend(amend) => self.amend(&amend),
+            Command::MassCancel(filter) => {
+                self.mass_cancel(&filter);
+                Ok(())
+            }
+            Command::OpenSession {
+                session_id,
+                user_id,
+            } => self.open_session(session_id, user_id),
+            Command::CloseSession { session_id } => self.close_session(session_id).map(|_| ()),
+            Command::Deposit {
+                user_id,
+                base,
+                quote,
+            } => self.deposit(user_id, base, quote),
+            // Every resting order leaves, as at the end of a run
+            Command::Shutdown => {
+                self.shutdown();
+                Ok(())
+            }
+        }
+    }
+}
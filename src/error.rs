@@ -0,0 +1,228 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use uuid::Uuid;
+
+/// Why an incoming order was refused before it reached the book.
+#[derive(Display, Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum RejectReason {
+    ZeroQuantity,
+    ZeroPrice,
+    /// Price multiplied by quantity does not fit into `u64`.
+    NotionalOverflow,
+    /// The order names a session that is not open.
+    UnknownSession,
+    /// The session of the order belongs to another user.
+    SessionUserMismatch,
+    /// The user does not have enough free funds to back the order.
+    InsufficientBalance,
+    /// An amendment would take the filled and remaining quantity of an
+    /// order together beyond `u64`.
+    QuantityOverflow,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum MatchError {
+    /// The order failed validation and was not processed.
+    Rejected(RejectReason),
+    /// An attempt to take more quantity from an order than it has left.
+    Overfill { requested: u64, available: u64 },
+    /// Two orders of the same side were about to be matched.
+    SameSide,
+    /// No open session has this id.
+    UnknownSession(u64),
+    /// A session with this id is already open.
+    SessionExists(u64),
+    /// The matcher does not keep account balances.
+    AccountsDisabled,
+    /// A balance of the user would go out of range.
+    BalanceOverflow(u64),
+    /// The free quote balance of the user does not cover its fee for a trade.
+    UnpaidFee(u64),
+    /// No resting order of the user has this id.
+    UnknownOrder(Uuid),
+    /// A matching policy chose a price outside the limits of the orders.
+    ExecutionPrice(u64),
+    /// A running notional total went out of range.
+    NotionalOverflow,
+}
+
+impl MatchError {
+    /// Whether the command was refused without touching the state of the
+    /// matcher, as opposed to failing halfway through.
+    pub fn is_refusal(&self) -> bool {
+        matches!(
+            self,
+            MatchError::Rejected(_)
+                | MatchError::UnknownSession(_)
+                | MatchError::SessionExists(_)
+                | MatchError::AccountsDisabled
+                | MatchError::UnknownOrder(_)
+        )
+    }
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::Rejected(reason) => write!(f, "order rejected: {}", reason),
+            MatchError::Overfill {
+                requested,
+                available,
+            } => write!(
+                f,
+                "trying to reduce {}, but only {} is available",
+                requested, available
+            ),
+            MatchError::SameSide => write!(f, "orders of the same side"),
+            MatchError::UnknownSession(id) => write!(f, "session {} is not open", id),
+            MatchError::SessionExists(id) => write!(f, "session {} is already open", id),
+            MatchError::AccountsDisabled => write!(f, "account balances are not enabled"),
+            MatchError::BalanceOverflow(user_id) => {
+                write!(f, "balance of user {} is out of range", user_id)
+            }
+            MatchError::UnpaidFee(user_id) => {
+                write!(f, "user {} cannot pay the fee of a trade", user_id)
+            }
+            MatchError::UnknownOrder(id) => write!(f, "no resting order {}", id),
+            MatchError::ExecutionPrice(price) => write!(
+                f,
+                "execution price {} is outside the limits of the orders",
+                price
+            ),
+            MatchError::NotionalOverflow => write!(f, "notional out of range"),
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum DecimalError {
+    /// The string is not a non-negative decimal number.
+    Invalid(String),
+    /// The value has more decimal places than the scale allows.
+    TooPrecise { value: String, scale: u32 },
+    /// The value does not fit into the fixed-point representation.
+    Overflow(String),
+    /// The scale itself is too large to be represented.
+    ScaleTooLarge(u32),
+}
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecimalError::Invalid(value) => write!(f, "'{}' is not a valid decimal", value),
+            DecimalError::TooPrecise { value, scale } => {
+                write!(f, "'{}' has more than {} decimal places", value, scale)
+            }
+            DecimalError::Overflow(value) => write!(f, "'{}' is out of range", value),
+            DecimalError::ScaleTooLarge(scale) => {
+                write!(f, "scale {} is too large", scale)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    /// The snapshot could not be encoded or decoded.
+    Format(String),
+    /// The snapshot was written in an unsupported format version.
+    Version(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot i/o error: {}", e),
+            SnapshotError::Format(e) => write!(f, "malformed snapshot: {}", e),
+            SnapshotError::Version(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> SnapshotError {
+        if e.is_io() {
+            return SnapshotError::Io(e.into());
+        }
+        SnapshotError::Format(e.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(std::io::Error),
+    /// A record could not be encoded or decoded.
+    Format(String),
+    /// A damaged record or a gap in sequence numbers before the end of the
+    /// journal.
+    Corrupt {
+        offset: usize,
+    },
+    /// Applying a command failed.
+    Match(MatchError),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(e) => write!(f, "journal i/o error: {}", e),
+            JournalError::Format(e) => write!(f, "malformed journal record: {}", e),
+            JournalError::Corrupt { offset } => {
+                write!(f, "journal is corrupt at offset {}", offset)
+            }
+            JournalError::Match(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<std::io::Error> for JournalError {
+    fn from(e: std::io::Error) -> JournalError {
+        JournalError::Io(e)
+    }
+}
+
+impl From<MatchError> for JournalError {
+    fn from(e: MatchError) -> JournalError {
+        JournalError::Match(e)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum FixError {
+    /// The bytes are not a FIX 4.4 message.
+    Malformed(String),
+    /// The checksum in the trailer does not match the message.
+    Checksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixError::Malformed(e) => write!(f, "malformed FIX message: {}", e),
+            FixError::Checksum { expected, actual } => {
+                write!(f, "FIX checksum is {:03}, expected {:03}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
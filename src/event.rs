@@ -0,0 +1,145 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+use crate::error::RejectReason;
+use crate::instrument::Instrument;
+use crate::order::{Order, OrderType};
+
+#[derive(Display, Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum EventKind {
+    Accepted,
+    Queued,
+    Rejected,
+    Canceled,
+    Executed,
+    PartiallyExecuted,
+    /// A resting order changed its price or quantity; the event carries the
+    /// order as amended.
+    Amended,
+}
+
+/// Something that happened to an order, together with the state of the order
+/// at that moment.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Position in the output of the matcher, set when the event is queued.
+    /// Trades are numbered in the same sequence.
+    pub seq: u64,
+    pub kind: EventKind,
+    pub order: Order,
+    pub reason: Option<RejectReason>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, order: &Order) -> Event {
+        Event {
+            seq: 0,
+            kind,
+            order: order.clone(),
+            reason: None,
+        }
+    }
+
+    pub fn rejected(order: &Order, reason: RejectReason) -> Event {
+        Event {
+            seq: 0,
+            kind: EventKind::Rejected,
+            order: order.clone(),
+            reason: Some(reason),
+        }
+    }
+
+    /// Event for an order that leaves the engine for good, chosen by how much
+    /// of it has been filled.
+    pub fn terminal(order: &Order) -> Event {
+        let kind = match order.order_type() {
+            OrderType::Ioc | OrderType::Lim => {
+                if order.current_qty() == 0 {
+                    EventKind::Executed
+                } else if order.current_qty() < order.initial_qty() {
+                    EventKind::PartiallyExecuted
+                } else {
+                    EventKind::Canceled
+                }
+            }
+            OrderType::Fok => {
+                if order.current_qty() != 0 {
+                    EventKind::Canceled
+                } else {
+                    EventKind::Executed
+                }
+            }
+        };
+        Event::new(kind, order)
+    }
+
+    /// Formats the event as a text line, writing price and quantity with the
+    /// scales of the given instrument.
+    pub fn to_line(&self, instrument: &Instrument) -> String {
+        let o = &self.order;
+        let mut line = format!(
+            "{},{},{},{},{},{}",
+            self.kind,
+            o.order_type(),
+            o.side(),
+            instrument.format_price(o.price()),
+            instrument.format_qty(o.initial_qty()),
+            o.user_id()
+        );
+        if let Some(reason) = self.reason {
+            line.push_str(&format!(",{}", reason));
+        }
+        line
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_line(&Instrument::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::Side;
+
+    #[test]
+    fn test_terminal_event_kind() {
+        let mut order = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        assert_eq!(Event::terminal(&order).kind, EventKind::Canceled);
+        order.reduce_quantity(4).unwrap();
+        assert_eq!(Event::terminal(&order).kind, EventKind::PartiallyExecuted);
+        order.reduce_quantity(6).unwrap();
+        assert_eq!(Event::terminal(&order).kind, EventKind::Executed);
+
+        let mut fok = Order::new(OrderType::Fok, Side::Sell, 100, 10, 1);
+        fok.reduce_quantity(4).unwrap();
+        assert_eq!(Event::terminal(&fok).kind, EventKind::Canceled);
+    }
+
+    #[test]
+    fn test_display_format() {
+        let order = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        assert_eq!(
+            Event::new(EventKind::Accepted, &order).to_string(),
+            "Accepted,Lim,Buy,100,10,1"
+        );
+        assert_eq!(
+            Event::rejected(&order, RejectReason::ZeroPrice).to_string(),
+            "Rejected,Lim,Buy,100,10,1,ZeroPrice"
+        );
+    }
+
+    #[test]
+    fn test_line_with_scales() {
+        let order = Order::new(OrderType::Lim, Side::Sell, 10025, 1500, 7);
+        let instrument = Instrument::new(2, 3).unwrap();
+        assert_eq!(
+            Event::new(EventKind::Queued, &order).to_line(&instrument),
+            "Queued,Lim,Sell,100.25,1.500,7"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// A fee charged on one side of a trade, in notional units. Negative rates
+/// are rebates paid to the user.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum FeeRate {
+    /// Basis points of the traded notional, rounded toward zero.
+    Bps(i64),
+    /// A fixed amount per quantity unit.
+    PerUnit(i64),
+}
+
+impl Default for FeeRate {
+    fn default() -> FeeRate {
+        FeeRate::Bps(0)
+    }
+}
+
+impl FeeRate {
+    pub fn fee(&self, price: u64, qty: u64) -> i128 {
+        match *self {
+            FeeRate::Bps(bps) => i128::from(price) * i128::from(qty) * i128::from(bps) / 10_000,
+            FeeRate::PerUnit(rate) => i128::from(qty) * i128::from(rate),
+        }
+    }
+}
+
+/// Parses `<n>bps` as basis points, anything else as a per-unit amount in
+/// notional units.
+impl FromStr for FeeRate {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<FeeRate, Self::Err> {
+        let s = s.trim();
+        match s.strip_suffix("bps") {
+            Some(bps) => bps.trim().parse().map(FeeRate::Bps),
+            None => s.parse().map(FeeRate::PerUnit),
+        }
+    }
+}
+
+/// Fees of the resting (maker) and the incoming (taker) side of a trade.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub maker: FeeRate,
+    pub taker: FeeRate,
+}
+
+/// Fee tiers by user. Users without a tier of their own pay the default
+/// one, which charges nothing unless configured.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    default: FeeTier,
+    users: BTreeMap<u64, FeeTier>,
+}
+
+impl FeeSchedule {
+    pub fn new(default: FeeTier) -> FeeSchedule {
+        FeeSchedule {
+            default,
+            users: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_user_tier(mut self, user_id: u64, tier: FeeTier) -> FeeSchedule {
+        self.users.insert(user_id, tier);
+        self
+    }
+
+    pub fn tier(&self, user_id: u64) -> FeeTier {
+        self.users.get(&user_id).copied().unwrap_or(self.default)
+    }
+
+    /// Fee of a user for its side of a trade.
+    pub fn fee(&self, user_id: u64, is_maker: bool, price: u64, qty: u64) -> i128 {
+        let tier = self.tier(user_id);
+        let rate = if is_maker { tier.maker } else { tier.taker };
+        rate.fee(price, qty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_rates() {
+        // 25 bps of 100 * 1000 = 250, and 1 bp of 100 * 15 = 0.15 rounds to 0
+        assert_eq!(FeeRate::Bps(25).fee(100, 1000), 250);
+        assert_eq!(FeeRate::Bps(1).fee(100, 15), 0);
+        assert_eq!(FeeRate::Bps(-1).fee(100, 1000), -10);
+        assert_eq!(FeeRate::PerUnit(3).fee(100, 7), 21);
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!("5bps".parse(), Ok(FeeRate::Bps(5)));
+        assert_eq!("-2bps".parse(), Ok(FeeRate::Bps(-2)));
+        assert_eq!("3".parse(), Ok(FeeRate::PerUnit(3)));
+        assert!("abc".parse::<FeeRate>().is_err());
+    }
+
+    #[test]
+    fn test_user_tiers() {
+        let schedule = FeeSchedule::new(FeeTier {
+            maker: FeeRate::Bps(-1),
+            taker: FeeRate::Bps(5),
+        })
+        .with_user_tier(
+            7,
+            FeeTier {
+                maker: FeeRate::Bps(-2),
+                taker: FeeRate::Bps(2),
+            },
+        );
+        assert_eq!(schedule.fee(1, true, 100, 100), -1);
+        assert_eq!(schedule.fee(1, false, 100, 100), 5);
+        assert_eq!(schedule.fee(7, true, 100, 100), -2);
+        assert_eq!(schedule.fee(7, false, 100, 100), 2);
+    }
+}
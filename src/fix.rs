@@ -0,0 +1,247 @@
+use crate::error::FixError;
+
+/// Field separator of the FIX tag=value encoding.
+pub const SOH: u8 = 0x01;
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+
+/// Largest BodyLength accepted, far above what any order-entry message
+/// needs.
+pub const MAX_MESSAGE: usize = 1 << 16;
+
+/// Tags used by the order-entry subset.
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+/// A FIX message: its fields in order, without the BeginString, BodyLength
+/// and CheckSum fields, which are added on encoding.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Message {
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Message {
+        Message {
+            fields: vec![(tag::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    /// The first value of a tag.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets a tag, replacing its value if present and appending it
+    /// otherwise.
+    pub fn set(&mut self, tag: u32, value: impl ToString) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(t, _)| *t == tag) {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value)),
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Message {
+        self.set(tag, value);
+        self
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.fields.iter().map(|(t, v)| (*t, v.as_str()))
+    }
+
+    /// The message on the wire, with header and trailer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+        let mut out = format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).into_bytes();
+        out.extend_from_slice(&body);
+        let sum = checksum(&out);
+        out.extend_from_slice(format!("10={:03}\x01", sum).as_bytes());
+        out
+    }
+
+    /// Reads the message at the start of `data`, returning it with the
+    /// number of bytes it takes, or `None` if more data is needed.
+    pub fn decode(data: &[u8]) -> Result<Option<(Message, usize)>, FixError> {
+        let malformed = |e: &str| FixError::Malformed(e.to_string());
+        let Some((begin, rest)) = split_field(data) else {
+            return Ok(None);
+        };
+        if begin != format!("8={}", BEGIN_STRING).as_bytes() {
+            return Err(malformed("message does not start with 8=FIX.4.4"));
+        }
+        let Some((length, _)) = split_field(rest) else {
+            return Ok(None);
+        };
+        let body_len: usize = length
+            .strip_prefix(b"9=")
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| malformed("BodyLength must follow BeginString"))?;
+        if body_len > MAX_MESSAGE {
+            return Err(malformed("BodyLength is too large"));
+        }
+        let body_start = begin.len() + length.len() + 2;
+        let Some(trailer_start) = body_start.checked_add(body_len) else {
+            return Err(malformed("BodyLength is too large"));
+        };
+        let end = trailer_start + 7;
+        if data.len() < end {
+            return Ok(None);
+        }
+        let trailer = &data[trailer_start..end];
+        if !trailer.starts_with(b"10=") || trailer[6] != SOH {
+            return Err(malformed("CheckSum must follow the body"));
+        }
+        let actual = std::str::from_utf8(&trailer[3..6])
+            .ok()
+            .and_then(|sum| sum.parse().ok())
+            .ok_or_else(|| malformed("CheckSum is not a number"))?;
+        let expected = checksum(&data[..trailer_start]);
+        if actual != expected {
+            return Err(FixError::Checksum { expected, actual });
+        }
+
+        let mut fields = Vec::new();
+        let mut body = &data[body_start..trailer_start];
+        while let Some((field, rest)) = split_field(body) {
+            let field = std::str::from_utf8(field).map_err(|_| malformed("field is not UTF-8"))?;
+            let (tag, value) = field
+                .split_once('=')
+                .and_then(|(tag, value)| Some((tag.parse().ok()?, value)))
+                .ok_or_else(|| FixError::Malformed(format!("bad field `{}`", field)))?;
+            fields.push((tag, value.to_string()));
+            body = rest;
+        }
+        if !body.is_empty() {
+            return Err(malformed("body does not end with a separator"));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tag::MSG_TYPE) {
+            return Err(malformed("MsgType must be the first field of the body"));
+        }
+        Ok(Some((Message { fields }, end)))
+    }
+}
+
+/// Sum of the bytes modulo 256.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// The bytes before the first separator, and the ones after it.
+fn split_field(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = data.iter().position(|b| *b == SOH)?;
+    Some((&data[..end], &data[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(s: &str) -> Vec<u8> {
+        s.replace('|', "\x01").into_bytes()
+    }
+
+    #[test]
+    fn test_encode() {
+        let message = Message::new("0").with(tag::MSG_SEQ_NUM, 2);
+        assert_eq!(message.encode(), wire("8=FIX.4.4|9=10|35=0|34=2|10=166|"));
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let message = Message::new("D")
+            .with(tag::CL_ORD_ID, "a-1")
+            .with(tag::PRICE, "100.5")
+            .with(tag::TEXT, "x=y");
+        let mut data = message.encode();
+        let len = data.len();
+        data.extend_from_slice(b"8=FIX");
+
+        let (decoded, used) = Message::decode(&data).unwrap().unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(used, len);
+        assert_eq!(decoded.get(tag::TEXT), Some("x=y"));
+        assert_eq!(decoded.msg_type(), "D");
+
+        // A message cut short waits for more data
+        for end in [0, 5, 12, len - 1] {
+            assert_eq!(Message::decode(&data[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(
+            Message::decode(&wire("8=FIX.4.2|9=5|35=0|10=000|")),
+            Err(FixError::Malformed(_))
+        ));
+        // Rejected before waiting for a body that long
+        let huge = format!("8=FIX.4.4|9={}|", usize::MAX);
+        assert!(matches!(
+            Message::decode(&wire(&huge)),
+            Err(FixError::Malformed(_))
+        ));
+        assert_eq!(
+            Message::decode(&wire("8=FIX.4.4|9=10|35=0|34=2|10=014|")),
+            Err(FixError::Checksum {
+                expected: 166,
+                actual: 14
+            })
+        );
+        let no_type = Message {
+            fields: vec![(tag::MSG_SEQ_NUM, "1".to_string())],
+        };
+        assert!(matches!(
+            Message::decode(&no_type.encode()),
+            Err(FixError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,956 @@
+//! The `fix` subcommand of the `matcher` binary: a gateway for a subset of
+//! FIX 4.4 order entry on the local host.
+//!
+//! A client logs on, then sends NewOrderSingle, OrderCancelRequest and
+//! OrderCancelReplaceRequest messages, which are answered with
+//! ExecutionReports and OrderCancelRejects. A session belongs to the
+//! SenderCompID of the client and outlives its connections: the sequence
+//! numbers and orders carry on when the client logs on again, and reports
+//! sent while it was away can be resent. A gap in the numbers of a client is
+//! answered with a ResendRequest, and a ResendRequest of a client is served
+//! from the messages sent to it so far.
+//!
+//! As with `serve`, messages are written to each client by a thread of its
+//! own, and the gateway stops if the engine fails in a way that may have
+//! left its state inconsistent.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use matcher::amend::Amend;
+use matcher::error::MatchError;
+use matcher::event::{Event, EventKind};
+use matcher::fix::{self, tag};
+use matcher::input::{parse_price, parse_qty, OrderBuilder, RecordType};
+use matcher::order::{OrderType, Side};
+use matcher::trade::Trade;
+use matcher::Matcher;
+use uuid::Uuid;
+
+use super::gateway::{merge, Client, Report};
+use super::{engine_args, matcher_from};
+
+pub fn command() -> Command {
+    Command::new("fix")
+        .about("Accept FIX 4.4 order entry from clients on the local host")
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .help("Port to listen on at 127.0.0.1; 0 picks a free one")
+                .value_parser(value_parser!(u16))
+                .default_value("9878"),
+        )
+        .arg(
+            Arg::new("comp-id")
+                .long("comp-id")
+                .help("SenderCompID of the messages of the gateway")
+                .default_value("MATCHER"),
+        )
+        .args(engine_args())
+}
+
+/// What the connection threads tell the matcher thread.
+enum Input {
+    Connected { conn: u64, stream: TcpStream },
+    Received { conn: u64, message: fix::Message },
+    Disconnected { conn: u64 },
+}
+
+/// A connection, and the client that logged on over it.
+struct Connection {
+    writer: Client,
+    client: Option<String>,
+    /// Set once the connection is to be closed.
+    closed: bool,
+}
+
+/// The session of a client, kept across its connections.
+#[derive(Default)]
+struct Session {
+    /// The connection the client is logged on over, if any.
+    conn: Option<u64>,
+    /// Sequence number expected of the next message of the client.
+    next_in: u64,
+    /// Whether a ResendRequest for a gap is outstanding.
+    resending: bool,
+    /// Bodies of the messages sent; the one numbered `n` is at `n - 1`.
+    sent: Vec<fix::Message>,
+}
+
+impl Session {
+    /// Numbers a message body and keeps it for resending, returning the
+    /// message to send.
+    fn send(&mut self, comp_id: &str, client: &str, body: fix::Message) -> fix::Message {
+        let message = stamp(comp_id, client, &body, self.sent.len() as u64 + 1, false);
+        self.sent.push(body);
+        message
+    }
+
+    /// The messages numbered `begin` to `end` again, or up to the last one
+    /// if `end` is 0. Application messages are sent as they were, flagged as
+    /// possible duplicates; runs of session messages are skipped with a
+    /// SequenceReset in gap fill mode.
+    fn resend(&self, comp_id: &str, client: &str, begin: u64, end: u64) -> Vec<fix::Message> {
+        let last = self.sent.len() as u64;
+        let end = if end == 0 { last } else { end.min(last) };
+        let gap_fill = |seq: u64, new_seq: u64| {
+            let body = fix::Message::new("4")
+                .with(tag::GAP_FILL_FLAG, "Y")
+                .with(tag::NEW_SEQ_NO, new_seq);
+            stamp(comp_id, client, &body, seq, true)
+        };
+        let mut messages = Vec::new();
+        let mut gap = None;
+        for seq in begin.max(1)..=end {
+            let body = &self.sent[seq as usize - 1];
+            if matches!(body.msg_type(), "8" | "9") {
+                if let Some(start) = gap.take() {
+                    messages.push(gap_fill(start, seq));
+                }
+                messages.push(stamp(comp_id, client, body, seq, true));
+            } else if gap.is_none() {
+                gap = Some(seq);
+            }
+        }
+        if let Some(start) = gap {
+            messages.push(gap_fill(start, end + 1));
+        }
+        messages
+    }
+}
+
+/// Adds the header to a message body, numbered `seq`.
+fn stamp(
+    comp_id: &str,
+    client: &str,
+    body: &fix::Message,
+    seq: u64,
+    poss_dup: bool,
+) -> fix::Message {
+    let mut message = fix::Message::new(body.msg_type())
+        .with(tag::SENDER_COMP_ID, comp_id)
+        .with(tag::TARGET_COMP_ID, client)
+        .with(tag::MSG_SEQ_NUM, seq)
+        .with(tag::SENDING_TIME, sending_time());
+    if poss_dup {
+        message.set(tag::POSS_DUP_FLAG, "Y");
+    }
+    for (tag, value) in body.fields().skip(1) {
+        message.set(tag, value);
+    }
+    message
+}
+
+/// An order entered through the gateway and still in the engine.
+#[derive(Debug, Clone)]
+struct FixOrder {
+    /// SenderCompID of the client that entered it.
+    client: String,
+    cl_ord_id: String,
+    symbol: String,
+    user_id: u64,
+    side: Side,
+    price: u64,
+    /// OrderQty of the client, which includes what was filled.
+    order_qty: u64,
+    cum_qty: u64,
+    /// Sum of price times quantity of the fills, for AvgPx.
+    notional: u128,
+}
+
+impl FixOrder {
+    fn ord_status(&self) -> &'static str {
+        if self.cum_qty == 0 {
+            "0"
+        } else {
+            "1"
+        }
+    }
+}
+
+/// The request whose reports are being sent.
+enum Request {
+    /// The order until the engine has given it an id.
+    New(Option<FixOrder>),
+    Cancel {
+        id: Uuid,
+        cl_ord_id: String,
+    },
+    Replace {
+        id: Uuid,
+        cl_ord_id: String,
+        price: u64,
+        order_qty: u64,
+    },
+}
+
+struct Gateway {
+    matcher: Matcher,
+    comp_id: String,
+    connections: HashMap<u64, Connection>,
+    /// Sessions by the SenderCompID of their client.
+    sessions: HashMap<String, Session>,
+    orders: HashMap<Uuid, FixOrder>,
+    /// Orders by the client that entered them and their ClOrdID.
+    ids: HashMap<(String, String), Uuid>,
+    exec_ids: u64,
+    /// An error of the engine other than a refusal, which stops the gateway.
+    failure: Option<MatchError>,
+}
+
+/// Runs the matcher on this thread until the process is stopped, or until
+/// the engine fails in a way that may have left its state inconsistent.
+/// Every connection gets a thread of its own that reads its messages.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut gateway = Gateway {
+        matcher: matcher_from(matches)?,
+        comp_id: matches.get_one::<String>("comp-id").unwrap().clone(),
+        connections: HashMap::new(),
+        sessions: HashMap::new(),
+        orders: HashMap::new(),
+        ids: HashMap::new(),
+        exec_ids: 0,
+        failure: None,
+    };
+    let port = *matches.get_one::<u16>("port").unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender));
+
+    for input in receiver {
+        match input {
+            Input::Connected { conn, stream } => match Client::start(stream) {
+                Ok(writer) => {
+                    let connection = Connection {
+                        writer,
+                        client: None,
+                        closed: false,
+                    };
+                    gateway.connections.insert(conn, connection);
+                }
+                Err(e) => eprintln!("Connection {}: {}", conn, e),
+            },
+            Input::Received { conn, message } => {
+                gateway.receive(conn, message);
+                if gateway.connections.get(&conn).is_some_and(|c| c.closed) {
+                    gateway.disconnect(conn);
+                }
+            }
+            Input::Disconnected { conn } => gateway.disconnect(conn),
+        }
+        if let Some(e) = gateway.failure.take() {
+            for (_, connection) in gateway.connections.drain() {
+                connection.writer.finish();
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+impl Gateway {
+    /// Sends a message body to a client, or keeps it for a resend if the
+    /// client is not connected.
+    fn send(&mut self, client: &str, body: fix::Message) {
+        let Some(session) = self.sessions.get_mut(client) else {
+            return;
+        };
+        let message = session.send(&self.comp_id, client, body);
+        if let Some(conn) = session.conn {
+            self.write(conn, &message);
+        }
+    }
+
+    /// Queues a message for a connection, which is closed if it has fallen
+    /// too far behind.
+    fn write(&mut self, conn: u64, message: &fix::Message) {
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            if !connection.writer.send(message.encode()) {
+                connection.closed = true;
+            }
+        }
+    }
+
+    /// Ends the session on a connection. A connection that never logged on
+    /// gets a Logout outside of any session.
+    fn logout(&mut self, conn: u64, text: &str) {
+        let Some(connection) = self.connections.get_mut(&conn) else {
+            return;
+        };
+        connection.closed = true;
+        let body = fix::Message::new("5").with(tag::TEXT, text);
+        match connection.client.clone() {
+            Some(client) => self.send(&client, body),
+            None => {
+                let message = stamp(&self.comp_id, "", &body, 1, false);
+                self.write(conn, &message);
+            }
+        }
+    }
+
+    /// Closes a connection once what is queued for it is written. The
+    /// session of its client stays.
+    fn disconnect(&mut self, conn: u64) {
+        let Some(connection) = self.connections.remove(&conn) else {
+            return;
+        };
+        if let Some(session) = connection
+            .client
+            .and_then(|client| self.sessions.get_mut(&client))
+        {
+            session.conn = None;
+        }
+    }
+
+    /// Logs a client on over a connection, starting its session or picking
+    /// it up again. Returns `false` if the session already has a connection.
+    fn logon(&mut self, conn: u64, client: &str, message: &fix::Message) -> bool {
+        let session = self
+            .sessions
+            .entry(client.to_string())
+            .or_insert_with(|| Session {
+                next_in: 1,
+                ..Session::default()
+            });
+        if session.conn.is_some() {
+            return false;
+        }
+        session.conn = Some(conn);
+        if let Some(connection) = self.connections.get_mut(&conn) {
+            connection.client = Some(client.to_string());
+        }
+        let logon = fix::Message::new("A").with(tag::ENCRYPT_METHOD, 0).with(
+            tag::HEART_BT_INT,
+            message.get(tag::HEART_BT_INT).unwrap_or("30"),
+        );
+        self.send(client, logon);
+        true
+    }
+
+    /// Checks the sequence number of a message and handles it if it is the
+    /// one expected. Messages beyond a gap are dropped, since the
+    /// ResendRequest for the gap asks for them again.
+    fn receive(&mut self, conn: u64, message: fix::Message) {
+        let Some(connection) = self.connections.get(&conn) else {
+            return;
+        };
+        let Some(seq) = message
+            .get(tag::MSG_SEQ_NUM)
+            .and_then(|seq| seq.parse::<u64>().ok())
+        else {
+            return self.logout(conn, "MsgSeqNum missing");
+        };
+        let msg_type = message.msg_type();
+        let client = match connection.client.clone() {
+            Some(client) => client,
+            None => {
+                let Some(client) = message.get(tag::SENDER_COMP_ID).filter(|_| msg_type == "A")
+                else {
+                    return self.logout(conn, "first message must be a Logon");
+                };
+                if !self.logon(conn, client, &message) {
+                    return self.logout(conn, "session already logged on");
+                }
+                client.to_string()
+            }
+        };
+        if msg_type == "4" && message.get(tag::GAP_FILL_FLAG) != Some("Y") {
+            // A reset moves the numbers whatever the number of the message
+            return self.sequence_reset(&client, seq, &message);
+        }
+        let Some(session) = self.sessions.get_mut(&client) else {
+            return;
+        };
+
+        if seq > session.next_in {
+            if !session.resending {
+                session.resending = true;
+                let resend = fix::Message::new("2")
+                    .with(tag::BEGIN_SEQ_NO, session.next_in)
+                    .with(tag::END_SEQ_NO, 0);
+                self.send(&client, resend);
+            }
+            return;
+        }
+        if seq < session.next_in {
+            if message.get(tag::POSS_DUP_FLAG) == Some("Y") {
+                return;
+            }
+            let text = format!(
+                "MsgSeqNum too low, expecting {} but received {}",
+                session.next_in, seq
+            );
+            return self.logout(conn, &text);
+        }
+        session.next_in += 1;
+        session.resending = false;
+
+        match msg_type {
+            "A" | "0" => {}
+            "1" => {
+                let heartbeat = fix::Message::new("0").with(
+                    tag::TEST_REQ_ID,
+                    message.get(tag::TEST_REQ_ID).unwrap_or_default(),
+                );
+                self.send(&client, heartbeat);
+            }
+            "2" => {
+                let number = |tag| {
+                    message
+                        .get(tag)
+                        .and_then(|n| n.parse().ok())
+                        .unwrap_or_default()
+                };
+                let resent = session.resend(
+                    &self.comp_id,
+                    &client,
+                    number(tag::BEGIN_SEQ_NO),
+                    number(tag::END_SEQ_NO),
+                );
+                for message in resent {
+                    self.write(conn, &message);
+                }
+            }
+            "4" => self.sequence_reset(&client, seq, &message),
+            "5" => self.logout(conn, "Logout"),
+            "D" => self.new_order(&client, &message),
+            "F" => self.cancel(&client, &message),
+            "G" => self.replace(&client, &message),
+            _ => {
+                let reject = fix::Message::new("3")
+                    .with(tag::REF_SEQ_NUM, seq)
+                    .with(tag::TEXT, format!("unsupported MsgType {}", msg_type));
+                self.send(&client, reject);
+            }
+        }
+    }
+
+    /// Moves the number expected of the next message of a client to the
+    /// NewSeqNo of a SequenceReset. Numbers never go back, so a NewSeqNo
+    /// below the one expected is answered with a Reject.
+    fn sequence_reset(&mut self, client: &str, seq: u64, message: &fix::Message) {
+        let Some(session) = self.sessions.get_mut(client) else {
+            return;
+        };
+        let new_seq = message.get(tag::NEW_SEQ_NO).and_then(|s| s.parse().ok());
+        match new_seq {
+            Some(new_seq) if new_seq >= session.next_in => {
+                session.next_in = new_seq;
+                session.resending = false;
+            }
+            _ => {
+                let text = format!(
+                    "NewSeqNo must be at least {}, the number expected",
+                    session.next_in
+                );
+                let reject = fix::Message::new("3")
+                    .with(tag::REF_SEQ_NUM, seq)
+                    .with(tag::TEXT, text);
+                self.send(client, reject);
+            }
+        }
+    }
+
+    fn new_order(&mut self, client: &str, message: &fix::Message) {
+        let order = self.parse_new_order(client, message).and_then(|order| {
+            let command = OrderBuilder {
+                order_type: RecordType::Order(order.1),
+                side: Some(order.0.side),
+                price: message.get(tag::PRICE).unwrap_or_default().to_string(),
+                initial_qty: message.get(tag::ORDER_QTY).unwrap_or_default().to_string(),
+                user_id: order.0.user_id,
+                session_id: None,
+                cancel_on_disconnect: None,
+            }
+            .build(self.matcher.instrument())?;
+            Ok((order.0, command))
+        });
+        match order {
+            Ok((order, command)) => {
+                let (cl_ord_id, symbol, side) =
+                    (order.cl_ord_id.clone(), order.symbol.clone(), order.side);
+                if let Err(text) = self.execute(command, Request::New(Some(order))) {
+                    let report = self.rejected_order(&cl_ord_id, &symbol, side_code(side), &text);
+                    self.send(client, report);
+                }
+            }
+            Err(text) => {
+                let report = self.rejected_order(
+                    message.get(tag::CL_ORD_ID).unwrap_or_default(),
+                    message.get(tag::SYMBOL).unwrap_or_default(),
+                    message.get(tag::SIDE).unwrap_or_default(),
+                    &text,
+                );
+                self.send(client, report);
+            }
+        }
+    }
+
+    fn parse_new_order(
+        &self,
+        client: &str,
+        message: &fix::Message,
+    ) -> Result<(FixOrder, OrderType), String> {
+        let field = |tag, name| {
+            message
+                .get(tag)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("{} missing", name))
+        };
+        let cl_ord_id = field(tag::CL_ORD_ID, "ClOrdID")?;
+        if self
+            .ids
+            .contains_key(&(client.to_string(), cl_ord_id.to_string()))
+        {
+            return Err(format!("duplicate ClOrdID {}", cl_ord_id));
+        }
+        let side = match field(tag::SIDE, "Side")? {
+            "1" => Side::Buy,
+            "2" => Side::Sell,
+            side => return Err(format!("unsupported Side {}", side)),
+        };
+        if field(tag::ORD_TYPE, "OrdType")? != "2" {
+            return Err("only limit orders (OrdType 2) are supported".to_string());
+        }
+        let order_type = match message.get(tag::TIME_IN_FORCE).unwrap_or("0") {
+            "0" | "1" => OrderType::Lim,
+            "3" => OrderType::Ioc,
+            "4" => OrderType::Fok,
+            tif => return Err(format!("unsupported TimeInForce {}", tif)),
+        };
+        let instrument = self.matcher.instrument();
+        let order = FixOrder {
+            client: client.to_string(),
+            cl_ord_id: cl_ord_id.to_string(),
+            symbol: message.get(tag::SYMBOL).unwrap_or_default().to_string(),
+            user_id: field(tag::ACCOUNT, "Account")?
+                .parse()
+                .map_err(|e| format!("Account {}", e))?,
+            side,
+            price: parse_price(instrument, "Price", field(tag::PRICE, "Price")?)?,
+            order_qty: parse_qty(instrument, "OrderQty", field(tag::ORDER_QTY, "OrderQty")?)?,
+            cum_qty: 0,
+            notional: 0,
+        };
+        Ok((order, order_type))
+    }
+
+    fn cancel(&mut self, client: &str, message: &fix::Message) {
+        let cl_ord_id = message.get(tag::CL_ORD_ID).unwrap_or_default();
+        let orig = message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default();
+        let Some((id, order)) = self.find(client, orig) else {
+            return self.cancel_rejected(client, message, "1", None, "1", "unknown order");
+        };
+        let command = matcher::command::Command::Cancel {
+            order_id: id,
+            user_id: order.user_id,
+        };
+        let request = Request::Cancel {
+            id,
+            cl_ord_id: cl_ord_id.to_string(),
+        };
+        self.execute_change(client, message, "1", command, request);
+    }
+
+    /// Replaces the price and quantity of an order. The new OrderQty counts
+    /// what was filled, so the order keeps the difference.
+    fn replace(&mut self, client: &str, message: &fix::Message) {
+        let orig = message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default();
+        let Some((id, order)) = self.find(client, orig) else {
+            return self.cancel_rejected(client, message, "2", None, "1", "unknown order");
+        };
+        let instrument = self.matcher.instrument();
+        let changes = message
+            .get(tag::ORDER_QTY)
+            .ok_or_else(|| "OrderQty missing".to_string())
+            .and_then(|qty| parse_qty(instrument, "OrderQty", qty))
+            .and_then(|qty| match message.get(tag::PRICE) {
+                Some(price) => Ok((parse_price(instrument, "Price", price)?, qty)),
+                None => Ok((order.price, qty)),
+            })
+            .and_then(|(price, qty)| match qty.checked_sub(order.cum_qty) {
+                Some(remaining) if remaining > 0 => Ok((price, qty, remaining)),
+                _ => Err("OrderQty must be above CumQty".to_string()),
+            });
+        let (price, order_qty, remaining) = match changes {
+            Ok(changes) => changes,
+            Err(text) => {
+                return self.cancel_rejected(client, message, "2", Some((id, &order)), "99", &text)
+            }
+        };
+        let command = matcher::command::Command::Amend(Amend {
+            order_id: id,
+            user_id: order.user_id,
+            price: Some(price),
+            qty: Some(remaining),
+        });
+        let request = Request::Replace {
+            id,
+            cl_ord_id: message.get(tag::CL_ORD_ID).unwrap_or_default().to_string(),
+            price,
+            order_qty,
+        };
+        self.execute_change(client, message, "2", command, request);
+    }
+
+    fn find(&self, client: &str, cl_ord_id: &str) -> Option<(Uuid, FixOrder)> {
+        let id = *self.ids.get(&(client.to_string(), cl_ord_id.to_string()))?;
+        Some((id, self.orders.get(&id)?.clone()))
+    }
+
+    /// Executes a cancel or a replace, answering a refusal of the engine
+    /// with an OrderCancelReject.
+    fn execute_change(
+        &mut self,
+        client: &str,
+        message: &fix::Message,
+        response_to: &str,
+        command: matcher::command::Command,
+        request: Request,
+    ) {
+        if let Err(text) = self.execute(command, request) {
+            self.cancel_rejected(client, message, response_to, None, "0", &text);
+        }
+    }
+
+    /// Executes a command and sends the reports of the orders it touched.
+    /// Returns the reason the engine refused it, if it did.
+    fn execute(
+        &mut self,
+        command: matcher::command::Command,
+        mut request: Request,
+    ) -> Result<(), String> {
+        let result = self.matcher.execute(command);
+        let events: Vec<Event> = self.matcher.drain_events().collect();
+        let trades: Vec<Trade> = self.matcher.drain_trades().collect();
+        for report in merge(&events, &trades) {
+            match report {
+                Report::Event(event) => self.event(event, &mut request),
+                Report::Trade(trade) => {
+                    self.fill(trade.taker_order_id, trade);
+                    self.fill(trade.maker_order_id, trade);
+                }
+            }
+        }
+        match result {
+            // The client learns of a rejection from the Rejected event
+            Ok(()) | Err(MatchError::Rejected(_)) => Ok(()),
+            Err(e) => {
+                let text = e.to_string();
+                if !e.is_refusal() {
+                    self.failure = Some(e);
+                }
+                Err(text)
+            }
+        }
+    }
+
+    fn event(&mut self, event: &Event, request: &mut Request) {
+        let id = event.order.id();
+        if let Request::New(order) = request {
+            if !self.orders.contains_key(&id) {
+                if let Some(order) = order.take() {
+                    self.ids
+                        .insert((order.client.clone(), order.cl_ord_id.clone()), id);
+                    self.orders.insert(id, order);
+                }
+            }
+        }
+        let Some(mut order) = self.orders.get(&id).cloned() else {
+            return;
+        };
+        let mut orig_cl_ord_id = None;
+        let (exec_type, ord_status) = match event.kind {
+            EventKind::Accepted => ("0", "0"),
+            EventKind::Queued => return,
+            // The last fill already reported the order as filled
+            EventKind::Executed => return self.forget(id),
+            EventKind::Amended => {
+                if let Request::Replace {
+                    id: replaced,
+                    cl_ord_id,
+                    price,
+                    order_qty,
+                } = request
+                {
+                    if *replaced == id {
+                        self.ids
+                            .remove(&(order.client.clone(), order.cl_ord_id.clone()));
+                        self.ids
+                            .insert((order.client.clone(), cl_ord_id.clone()), id);
+                        orig_cl_ord_id = Some(order.cl_ord_id.clone());
+                        order.cl_ord_id = cl_ord_id.clone();
+                        order.price = *price;
+                        order.order_qty = *order_qty;
+                        self.orders.insert(id, order.clone());
+                    }
+                }
+                ("5", order.ord_status())
+            }
+            EventKind::Rejected => {
+                // A rejected replace leaves the order as it was
+                if let Request::Replace {
+                    id: replaced,
+                    cl_ord_id,
+                    ..
+                } = request
+                {
+                    if *replaced == id {
+                        let text = event.reason.map(|r| r.to_string()).unwrap_or_default();
+                        let reject = self
+                            .cancel_reject(&order.cl_ord_id, Some((id, &order)), "2", "99", &text)
+                            .with(tag::CL_ORD_ID, cl_ord_id.clone());
+                        return self.send(&order.client, reject);
+                    }
+                }
+                self.forget(id);
+                ("8", "8")
+            }
+            EventKind::Canceled | EventKind::PartiallyExecuted => {
+                if let Request::Cancel {
+                    id: canceled,
+                    cl_ord_id,
+                } = request
+                {
+                    if *canceled == id {
+                        orig_cl_ord_id = Some(order.cl_ord_id.clone());
+                        order.cl_ord_id = cl_ord_id.clone();
+                    }
+                }
+                self.forget(id);
+                ("4", "4")
+            }
+        };
+        let mut report = self.report(&order, id, exec_type, ord_status);
+        if let Some(orig) = orig_cl_ord_id {
+            report.set(tag::ORIG_CL_ORD_ID, orig);
+        }
+        if let Some(reason) = event.reason {
+            report.set(tag::TEXT, reason);
+        }
+        self.send(&order.client, report);
+    }
+
+    fn fill(&mut self, id: Uuid, trade: &Trade) {
+        let Some(order) = self.orders.get_mut(&id) else {
+            return;
+        };
+        order.cum_qty += trade.qty;
+        order.notional += u128::from(trade.price) * u128::from(trade.qty);
+        let order = order.clone();
+        let ord_status = if order.cum_qty >= order.order_qty {
+            "2"
+        } else {
+            "1"
+        };
+        let instrument = *self.matcher.instrument();
+        let report = self
+            .report(&order, id, "F", ord_status)
+            .with(tag::LAST_PX, instrument.format_price(trade.price))
+            .with(tag::LAST_QTY, instrument.format_qty(trade.qty));
+        self.send(&order.client, report);
+    }
+
+    fn forget(&mut self, id: Uuid) {
+        if let Some(order) = self.orders.remove(&id) {
+            self.ids.remove(&(order.client, order.cl_ord_id));
+        }
+    }
+
+    /// An ExecutionReport. Orders that are done have nothing left.
+    fn report(
+        &mut self,
+        order: &FixOrder,
+        id: Uuid,
+        exec_type: &str,
+        ord_status: &str,
+    ) -> fix::Message {
+        let instrument = self.matcher.instrument();
+        let leaves = match ord_status {
+            "4" | "8" => 0,
+            _ => order.order_qty.saturating_sub(order.cum_qty),
+        };
+        let avg_px = match order.notional.checked_div(u128::from(order.cum_qty)) {
+            Some(avg) => instrument.format_price(avg as u64),
+            None => "0".to_string(),
+        };
+        self.exec_ids += 1;
+        fix::Message::new("8")
+            .with(tag::ORDER_ID, id)
+            .with(tag::CL_ORD_ID, &order.cl_ord_id)
+            .with(tag::EXEC_ID, self.exec_ids)
+            .with(tag::EXEC_TYPE, exec_type)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::SYMBOL, &order.symbol)
+            .with(tag::SIDE, side_code(order.side))
+            .with(tag::ORDER_QTY, instrument.format_qty(order.order_qty))
+            .with(tag::PRICE, instrument.format_price(order.price))
+            .with(tag::LEAVES_QTY, instrument.format_qty(leaves))
+            .with(tag::CUM_QTY, instrument.format_qty(order.cum_qty))
+            .with(tag::AVG_PX, avg_px)
+    }
+
+    /// An ExecutionReport for an order that never reached the book.
+    fn rejected_order(
+        &mut self,
+        cl_ord_id: &str,
+        symbol: &str,
+        side: &str,
+        text: &str,
+    ) -> fix::Message {
+        self.exec_ids += 1;
+        fix::Message::new("8")
+            .with(tag::ORDER_ID, "NONE")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::EXEC_ID, self.exec_ids)
+            .with(tag::EXEC_TYPE, "8")
+            .with(tag::ORD_STATUS, "8")
+            .with(tag::SYMBOL, symbol)
+            .with(tag::SIDE, side)
+            .with(tag::LEAVES_QTY, 0)
+            .with(tag::CUM_QTY, 0)
+            .with(tag::AVG_PX, 0)
+            .with(tag::TEXT, text)
+    }
+
+    fn cancel_rejected(
+        &mut self,
+        client: &str,
+        message: &fix::Message,
+        response_to: &str,
+        order: Option<(Uuid, &FixOrder)>,
+        reason: &str,
+        text: &str,
+    ) {
+        let reject = self
+            .cancel_reject(
+                message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default(),
+                order,
+                response_to,
+                reason,
+                text,
+            )
+            .with(
+                tag::CL_ORD_ID,
+                message.get(tag::CL_ORD_ID).unwrap_or_default(),
+            );
+        self.send(client, reject);
+    }
+
+    /// An OrderCancelReject, without its ClOrdID. `response_to` is 1 for a
+    /// cancel and 2 for a replace; `reason` is a CxlRejReason.
+    fn cancel_reject(
+        &self,
+        orig_cl_ord_id: &str,
+        order: Option<(Uuid, &FixOrder)>,
+        response_to: &str,
+        reason: &str,
+        text: &str,
+    ) -> fix::Message {
+        let (order_id, ord_status) = match order {
+            Some((id, order)) => (id.to_string(), order.ord_status()),
+            None => ("NONE".to_string(), "8"),
+        };
+        fix::Message::new("9")
+            .with(tag::ORDER_ID, order_id)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::CXL_REJ_REASON, reason)
+            .with(tag::TEXT, text)
+    }
+}
+
+fn side_code(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    }
+}
+
+/// The current time in UTC as FIX writes it, e.g. `20240102-13:45:00.123`.
+fn sending_time() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+/// The date of a day counted from 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn accept(listener: TcpListener, sender: Sender<Input>) {
+    for (conn, stream) in (1..).zip(listener.incoming()) {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if let Err(e) = read_client(conn, stream, &sender) {
+                eprintln!("Connection {}: {}", conn, e);
+            }
+            let _ = sender.send(Input::Disconnected { conn });
+        });
+    }
+}
+
+/// Reads the messages of one client until it disconnects. A message that
+/// cannot be read ends the connection, since what follows it cannot be
+/// trusted either.
+fn read_client(
+    conn: u64,
+    mut stream: TcpStream,
+    sender: &Sender<Input>,
+) -> Result<(), Box<dyn Error>> {
+    sender.send(Input::Connected {
+        conn,
+        stream: stream.try_clone()?,
+    })?;
+    let mut data = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        while let Some((message, used)) = fix::Message::decode(&data)? {
+            data.drain(..used);
+            sender.send(Input::Received { conn, message })?;
+        }
+        if data.len() > fix::MAX_MESSAGE {
+            return Err(format!("message longer than {} bytes", fix::MAX_MESSAGE).into());
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        data.extend_from_slice(&chunk[..read]);
+    }
+}
@@ -0,0 +1,439 @@
+//! The `serve` subcommand of the `matcher` binary: a TCP gateway on the
+//! local host feeding the orders of many clients to one matcher.
+//!
+//! Clients send the commands of JSON Lines input, either one per line or
+//! each in a frame that starts with its length as a big-endian `u32`. The
+//! first byte a client sends decides which: `{` or white space means lines.
+//! Reports go back in the same framing, as records of the JSONL output
+//! format, to the connection that entered the order they are about. Only
+//! that connection may cancel or amend the order, and a mass cancel only
+//! reaches the orders of the connection sending it. Sessions likewise
+//! belong to the connection that opened them.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use matcher::error::MatchError;
+use matcher::event::{Event, EventKind};
+use matcher::input::JsonCommand;
+use matcher::instrument::Instrument;
+use matcher::order::Side;
+use matcher::output::{EventWriter, Format};
+use matcher::trade::Trade;
+use uuid::Uuid;
+
+use super::{engine_args, matcher_from};
+
+/// Largest frame or line a client may send.
+const MAX_FRAME: u32 = 1 << 20;
+
+/// Records waiting for a client before it counts as too slow and is
+/// dropped.
+const QUEUE_LEN: usize = 1024;
+
+/// How long writing to a client may stall before it is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn command() -> Command {
+    Command::new("serve")
+        .about("Accept orders from TCP clients on the local host")
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .help("Port to listen on at 127.0.0.1; 0 picks a free one")
+                .value_parser(value_parser!(u16))
+                .default_value("7878"),
+        )
+        .args(engine_args())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Framing {
+    Lines,
+    Frames,
+}
+
+/// What the connection threads tell the matcher thread.
+enum Message {
+    Connected {
+        conn: u64,
+        stream: TcpStream,
+        framing: Framing,
+    },
+    Command {
+        conn: u64,
+        command: Result<matcher::command::Command, String>,
+    },
+    Disconnected {
+        conn: u64,
+    },
+}
+
+/// A connected client. What it is sent is written by a thread of its own,
+/// so that a client that does not keep up never holds up the matcher. The
+/// connection is closed once the client is dropped and everything queued
+/// for it is written.
+pub struct Client {
+    queue: SyncSender<Vec<u8>>,
+    /// Kept to cut the connection without waiting for the writer.
+    stream: TcpStream,
+    writer: JoinHandle<()>,
+}
+
+impl Client {
+    pub fn start(stream: TcpStream) -> io::Result<Client> {
+        let (queue, records) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LEN);
+        let mut out = stream.try_clone()?;
+        out.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let writer = thread::spawn(move || {
+            for data in records {
+                if out.write_all(&data).is_err() {
+                    break;
+                }
+            }
+            let _ = out.shutdown(Shutdown::Both);
+        });
+        Ok(Client {
+            queue,
+            stream,
+            writer,
+        })
+    }
+
+    /// Queues data for the client. Returns `false` if the client has fallen
+    /// too far behind or cannot be written to any more, and should be
+    /// dropped.
+    pub fn send(&self, data: Vec<u8>) -> bool {
+        self.queue.try_send(data).is_ok()
+    }
+
+    /// Cuts the connection at once, dropping what is still queued.
+    pub fn close(self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    /// Writes out what is still queued, then closes the connection.
+    pub fn finish(self) {
+        drop(self.queue);
+        let _ = self.writer.join();
+    }
+}
+
+/// Puts one record, given as a line ending in a newline, into the framing
+/// of a client.
+fn frame(framing: Framing, line: Vec<u8>) -> Vec<u8> {
+    match framing {
+        Framing::Lines => line,
+        Framing::Frames => {
+            let payload = line.strip_suffix(b"\n").unwrap_or(&line);
+            let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(payload);
+            frame
+        }
+    }
+}
+
+/// One piece of the output of a matcher.
+#[derive(Debug, Copy, Clone)]
+pub enum Report<'a> {
+    Event(&'a Event),
+    Trade(&'a Trade),
+}
+
+/// The events and trades of a matcher in the order they happened.
+pub fn merge<'a>(events: &'a [Event], trades: &'a [Trade]) -> Vec<Report<'a>> {
+    let mut reports: Vec<Report> = events
+        .iter()
+        .map(Report::Event)
+        .chain(trades.iter().map(Report::Trade))
+        .collect();
+    reports.sort_by_key(|report| match report {
+        Report::Event(event) => event.seq,
+        Report::Trade(trade) => trade.seq,
+    });
+    reports
+}
+
+/// Remembers which connection entered each order still in the engine, so
+/// that its reports reach that connection even when another client's order
+/// trades against it, and which connection opened each session.
+#[derive(Default)]
+pub struct Router {
+    owners: HashMap<Uuid, u64>,
+    sessions: HashMap<u64, u64>,
+}
+
+impl Router {
+    /// Runs a command of `conn` on its own orders and sessions only. Orders
+    /// and sessions of other connections are treated as if they did not
+    /// exist, and a mass cancel leaves them alone.
+    pub fn execute(
+        &mut self,
+        conn: u64,
+        matcher: &mut matcher::Matcher,
+        command: matcher::command::Command,
+    ) -> Result<(), MatchError> {
+        use matcher::command::Command;
+
+        let check_order = |id: Uuid| match self.owners.get(&id) {
+            Some(&owner) if owner == conn => Ok(()),
+            _ => Err(MatchError::UnknownOrder(id)),
+        };
+        let check_session = |session_id: u64| match self.sessions.get(&session_id) {
+            Some(&owner) if owner == conn => Ok(()),
+            _ => Err(MatchError::UnknownSession(session_id)),
+        };
+        match command {
+            Command::Cancel { order_id, .. } => check_order(order_id)?,
+            Command::Amend(ref amend) => check_order(amend.order_id)?,
+            Command::NewOrder(ref o) => {
+                if let Some(session_id) = o.session_id() {
+                    check_session(session_id)?;
+                }
+            }
+            Command::CloseSession { session_id } => check_session(session_id)?,
+            Command::MassCancel(filter) => {
+                let mine: Vec<Uuid> = [Side::Buy, Side::Sell]
+                    .into_iter()
+                    .flat_map(|side| matcher.book().orders(side))
+                    .filter(|o| filter.matches(o) && check_order(o.id()).is_ok())
+                    .map(|o| o.id())
+                    .collect();
+                for order_id in mine {
+                    matcher.cancel_order(order_id, filter.user_id)?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        match command {
+            Command::OpenSession { session_id, .. } => {
+                matcher.execute(command)?;
+                self.sessions.insert(session_id, conn);
+                Ok(())
+            }
+            Command::CloseSession { session_id } => {
+                matcher.execute(command)?;
+                self.sessions.remove(&session_id);
+                Ok(())
+            }
+            command => matcher.execute(command),
+        }
+    }
+
+    /// Pairs the output of a command from `conn` with the connections it
+    /// goes to, in the order it happened. Orders seen for the first time
+    /// belong to `conn`; a trade goes to the owners of both orders.
+    pub fn route<'a>(
+        &mut self,
+        conn: u64,
+        events: &'a [Event],
+        trades: &'a [Trade],
+    ) -> Vec<(u64, Report<'a>)> {
+        let mut routed = Vec::new();
+        for report in merge(events, trades) {
+            match report {
+                Report::Event(event) => {
+                    let id = event.order.id();
+                    let owner = *self.owners.entry(id).or_insert(conn);
+                    // Only orders leaving the engine produce other events
+                    if !matches!(
+                        event.kind,
+                        EventKind::Accepted | EventKind::Queued | EventKind::Amended
+                    ) {
+                        self.owners.remove(&id);
+                    }
+                    routed.push((owner, report));
+                }
+                Report::Trade(trade) => {
+                    let taker = self
+                        .owners
+                        .get(&trade.taker_order_id)
+                        .copied()
+                        .unwrap_or(conn);
+                    routed.push((taker, report));
+                    if let Some(&maker) = self.owners.get(&trade.maker_order_id) {
+                        if maker != taker {
+                            routed.push((maker, report));
+                        }
+                    }
+                }
+            }
+        }
+        routed
+    }
+}
+
+/// Runs the matcher on this thread until the process is stopped, or until
+/// the engine fails in a way that may have left its state inconsistent.
+/// Every connection gets a thread of its own that reads its commands.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut matcher = matcher_from(matches)?;
+    let instrument = *matcher.instrument();
+    let port = *matches.get_one::<u16>("port").unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender, instrument));
+
+    let mut clients: HashMap<u64, (Client, Framing)> = HashMap::new();
+    let mut router = Router::default();
+    for message in receiver {
+        let (conn, command) = match message {
+            Message::Connected {
+                conn,
+                stream,
+                framing,
+            } => {
+                match Client::start(stream) {
+                    Ok(client) => {
+                        clients.insert(conn, (client, framing));
+                    }
+                    Err(e) => eprintln!("Connection {}: {}", conn, e),
+                }
+                continue;
+            }
+            Message::Disconnected { conn } => {
+                // Its writer stops once the records queued for it are out
+                clients.remove(&conn);
+                continue;
+            }
+            Message::Command { conn, command } => (conn, command),
+        };
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => {
+                send_error(&mut clients, conn, &e);
+                continue;
+            }
+        };
+        let result = router.execute(conn, &mut matcher, command);
+        let events: Vec<Event> = matcher.drain_events().collect();
+        let trades: Vec<Trade> = matcher.drain_trades().collect();
+        for (owner, report) in router.route(conn, &events, &trades) {
+            let mut writer = EventWriter::new(Vec::new(), Format::Jsonl, instrument);
+            match report {
+                Report::Event(event) => writer.write_event(event)?,
+                Report::Trade(trade) => writer.write_trade(trade)?,
+            }
+            send(&mut clients, owner, writer.into_inner()?);
+        }
+        match result {
+            // The client learns of a rejection from the Rejected event
+            Ok(()) | Err(MatchError::Rejected(_)) => {}
+            Err(e) if e.is_refusal() => send_error(&mut clients, conn, &e.to_string()),
+            Err(e) => {
+                send_error(&mut clients, conn, &e.to_string());
+                for (_, (client, _)) in clients.drain() {
+                    client.finish();
+                }
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn send_error(clients: &mut HashMap<u64, (Client, Framing)>, conn: u64, message: &str) {
+    let mut line = serde_json::json!({ "type": "Error", "message": message }).to_string();
+    line.push('\n');
+    send(clients, conn, line.into_bytes());
+}
+
+/// Queues a record for a client. A client that cannot be reached any more,
+/// or that has fallen too far behind, is dropped. Reports for clients that
+/// are gone are dropped.
+fn send(clients: &mut HashMap<u64, (Client, Framing)>, conn: u64, line: Vec<u8>) {
+    let Some((client, framing)) = clients.get(&conn) else {
+        return;
+    };
+    if !client.send(frame(*framing, line)) {
+        if let Some((client, _)) = clients.remove(&conn) {
+            client.close();
+        }
+    }
+}
+
+fn accept(listener: TcpListener, sender: Sender<Message>, instrument: Instrument) {
+    for (conn, stream) in (1..).zip(listener.incoming()) {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if let Err(e) = read_client(conn, stream, &sender, &instrument) {
+                eprintln!("Connection {}: {}", conn, e);
+            }
+            let _ = sender.send(Message::Disconnected { conn });
+        });
+    }
+}
+
+/// Reads the commands of one client until it disconnects. A command that
+/// cannot be read is reported to the client, which may go on; a frame that
+/// cannot be read, or a line or frame that is too long, ends the
+/// connection.
+fn read_client(
+    conn: u64,
+    stream: TcpStream,
+    sender: &Sender<Message>,
+    instrument: &Instrument,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let framing = match reader.fill_buf()?.first() {
+        None => return Ok(()),
+        Some(b) if *b == b'{' || b.is_ascii_whitespace() => Framing::Lines,
+        Some(_) => Framing::Frames,
+    };
+    sender.send(Message::Connected {
+        conn,
+        stream,
+        framing,
+    })?;
+
+    loop {
+        let text = match framing {
+            Framing::Lines => {
+                let mut line = String::new();
+                let read = (&mut reader)
+                    .take(u64::from(MAX_FRAME) + 1)
+                    .read_line(&mut line)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                if read > MAX_FRAME as usize {
+                    return Err(format!("line of more than {} bytes is too long", MAX_FRAME).into());
+                }
+                line
+            }
+            Framing::Frames => {
+                let mut len = [0; 4];
+                match reader.read_exact(&mut len) {
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                    result => result?,
+                }
+                let len = u32::from_be_bytes(len);
+                if len > MAX_FRAME {
+                    return Err(format!("frame of {} bytes is too large", len).into());
+                }
+                let mut payload = vec![0; len as usize];
+                reader.read_exact(&mut payload)?;
+                String::from_utf8(payload)?
+            }
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let command = serde_json::from_str::<JsonCommand>(&text)
+            .map_err(|e| e.to_string())
+            .and_then(|c| c.build(instrument));
+        sender.send(Message::Command { conn, command })?;
+    }
+}
@@ -0,0 +1,105 @@
+//! The `gen` subcommand of the `matcher` binary: random order flow in the
+//! CSV input format, for trying out and benchmarking the engine.
+
+use std::error::Error;
+use std::io::{self, BufWriter};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use csv::Writer;
+use matcher::order::{splitmix64, OrderType, Side};
+
+pub fn command() -> Command {
+    Command::new("gen")
+        .about("Write random orders to stdout as CSV input")
+        .arg(
+            Arg::new("count")
+                .long("count")
+                .help("Number of orders")
+                .value_parser(value_parser!(u64))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .help("Seed of the generator; the same seed gives the same orders")
+                .value_parser(value_parser!(u64))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("users")
+                .long("users")
+                .help("Number of users placing orders")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("mid")
+                .long("mid")
+                .help("Price around which orders are placed")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("depth")
+                .long("depth")
+                .help("Price levels on each side of the mid that orders are spread over")
+                .value_parser(value_parser!(u64))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("max-qty")
+                .long("max-qty")
+                .help("Largest order quantity")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("100"),
+        )
+}
+
+/// The state of the pseudo-random generator the orders are drawn from.
+struct Rng(u64);
+
+impl Rng {
+    /// A number in `0..n`, with a bias too small to matter here.
+    fn below(&mut self, n: u64) -> u64 {
+        splitmix64(&mut self.0) % n
+    }
+}
+
+/// Mostly limit orders, with buys a little below the mid and sells a
+/// little above, overlapping enough that a good share of them trade.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let arg = |name: &str| *matches.get_one::<u64>(name).unwrap();
+    let (mid, depth) = (arg("mid"), arg("depth"));
+    let mut rng = Rng(arg("seed"));
+    let mut writer = Writer::from_writer(BufWriter::new(io::stdout().lock()));
+    writer.write_record(["order_type", "side", "price", "initial_qty", "user_id"])?;
+    for _ in 0..arg("count") {
+        let order_type = match rng.below(20) {
+            0 => OrderType::Fok,
+            1..=3 => OrderType::Ioc,
+            _ => OrderType::Lim,
+        };
+        // Prices stay within `1..=u64::MAX` however large the arguments
+        let offset = rng.below(depth.saturating_add(1));
+        let (side, price) = if rng.below(2) == 0 {
+            (
+                Side::Buy,
+                mid.saturating_add(depth / 4).saturating_sub(offset),
+            )
+        } else {
+            (
+                Side::Sell,
+                mid.saturating_add(offset).saturating_sub(depth / 4),
+            )
+        };
+        writer.write_record([
+            order_type.to_string(),
+            side.to_string(),
+            price.max(1).to_string(),
+            (1 + rng.below(arg("max-qty"))).to_string(),
+            (1 + rng.below(arg("users"))).to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
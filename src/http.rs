@@ -0,0 +1,406 @@
+//! The `http` subcommand of the `matcher` binary: a JSON API on the local
+//! host to enter and cancel orders and to look at the book and the trades.
+//!
+//! Every request is answered on a connection of its own, which is closed
+//! after the response. Order events and trades are sent as the records of
+//! the JSONL output format.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use matcher::error::MatchError;
+use matcher::event::Event;
+use matcher::input::{DecimalText, JsonCommand};
+use matcher::instrument::Instrument;
+use matcher::order::{OrderType, Side};
+use matcher::output::{EventWriter, Format};
+use matcher::trade::Trade;
+use matcher::Matcher;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::gateway::{merge, Report};
+use super::{engine_args, matcher_from, OrderReport};
+
+/// Largest request head and body a client may send.
+const MAX_HEAD: usize = 1 << 16;
+const MAX_BODY: usize = 1 << 20;
+
+pub fn command() -> Command {
+    Command::new("http")
+        .about("Serve a JSON API over HTTP on the local host")
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .help("Port to listen on at 127.0.0.1; 0 picks a free one")
+                .value_parser(value_parser!(u16))
+                .default_value("8080"),
+        )
+        .arg(
+            Arg::new("trade-history")
+                .long("trade-history")
+                .help("Number of recent trades kept for GET /trades")
+                .value_parser(value_parser!(usize))
+                .default_value("1000"),
+        )
+        .args(engine_args())
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// A query parameter, if given.
+    fn param<T: FromStr>(&self, name: &str) -> Result<Option<T>, Response>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.query
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| Response::error(400, format!("{} {}", name, e)))
+            })
+            .transpose()
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl ToString) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+}
+
+/// A request handed to the matcher thread, with where to send the answer.
+struct Call {
+    request: Request,
+    reply: Sender<Response>,
+}
+
+/// The body of `POST /orders`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewOrder {
+    order_type: OrderType,
+    side: Side,
+    price: DecimalText,
+    initial_qty: DecimalText,
+    user_id: u64,
+}
+
+struct Api {
+    matcher: Matcher,
+    /// The most recent trades, oldest first.
+    trades: VecDeque<Value>,
+    history: usize,
+}
+
+/// Runs the matcher on this thread until the process is stopped. Every
+/// connection gets a thread of its own that reads its request.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut api = Api {
+        matcher: matcher_from(matches)?,
+        trades: VecDeque::new(),
+        history: *matches.get_one::<usize>("trade-history").unwrap(),
+    };
+    let port = *matches.get_one::<u16>("port").unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender));
+    for call in receiver {
+        let _ = call.reply.send(api.handle(&call.request));
+    }
+    Ok(())
+}
+
+impl Api {
+    fn handle(&mut self, request: &Request) -> Response {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["orders"]) => self.new_order(request),
+            ("DELETE", ["orders", order_id]) => self.cancel(request, order_id),
+            ("GET", ["book", "l2"]) => self.levels(request),
+            ("GET", ["book", "l3"]) => Ok(self.book_orders()),
+            ("GET", ["users", user_id, "orders"]) => self.user_orders(user_id),
+            ("GET", ["trades"]) => self.recent_trades(request),
+            (
+                _,
+                ["orders"]
+                | ["orders", _]
+                | ["book", "l2" | "l3"]
+                | ["users", _, "orders"]
+                | ["trades"],
+            ) => Err(Response::error(405, "method not allowed")),
+            _ => Err(Response::error(404, "not found")),
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    fn new_order(&mut self, request: &Request) -> Result<Response, Response> {
+        let order: NewOrder =
+            serde_json::from_slice(&request.body).map_err(|e| Response::error(400, e))?;
+        let command = JsonCommand::New {
+            order_type: order.order_type,
+            side: order.side,
+            price: order.price,
+            initial_qty: order.initial_qty,
+            user_id: order.user_id,
+            session_id: None,
+            cancel_on_disconnect: None,
+        }
+        .build(self.matcher.instrument())
+        .map_err(|e| Response::error(400, e))?;
+        Ok(self.execute(command))
+    }
+
+    fn cancel(&mut self, request: &Request, order_id: &str) -> Result<Response, Response> {
+        let order_id = order_id
+            .parse()
+            .map_err(|e| Response::error(400, format!("order id {}", e)))?;
+        let user_id = request
+            .param("user_id")?
+            .ok_or_else(|| Response::error(400, "user_id missing"))?;
+        Ok(self.execute(matcher::command::Command::Cancel { order_id, user_id }))
+    }
+
+    /// Executes a command, answering with the events and trades it caused.
+    /// A rejected order still has its Rejected event in the answer.
+    fn execute(&mut self, command: matcher::command::Command) -> Response {
+        let result = self.matcher.execute(command);
+        let events: Vec<Event> = self.matcher.drain_events().collect();
+        let trades: Vec<Trade> = self.matcher.drain_trades().collect();
+        let instrument = *self.matcher.instrument();
+        let mut reports = Vec::new();
+        for report in merge(&events, &trades) {
+            let record = record(instrument, report);
+            if let Report::Trade(_) = report {
+                self.trades.push_back(record.clone());
+                if self.trades.len() > self.history {
+                    self.trades.pop_front();
+                }
+            }
+            reports.push(record);
+        }
+        let mut body = json!({ "reports": reports });
+        let Err(e) = result else {
+            return Response::ok(body);
+        };
+        let status = match e {
+            MatchError::Rejected(_) => 422,
+            MatchError::UnknownOrder(_) => 404,
+            _ if e.is_refusal() => 409,
+            _ => 500,
+        };
+        body["error"] = json!(e.to_string());
+        Response { status, body }
+    }
+
+    /// Price levels of both sides from the best price on, at most `depth`
+    /// of them if given.
+    fn levels(&self, request: &Request) -> Result<Response, Response> {
+        let depth = request.param("depth")?.unwrap_or(usize::MAX);
+        let instrument = self.matcher.instrument();
+        let side = |side| -> Vec<Value> {
+            self.matcher
+                .book()
+                .depth(side)
+                .take(depth)
+                .map(|(price, qty, orders)| {
+                    json!({
+                        "price": instrument.format_price(price),
+                        "qty": instrument.format_qty(qty),
+                        "orders": orders,
+                    })
+                })
+                .collect()
+        };
+        Ok(Response::ok(
+            json!({ "bids": side(Side::Buy), "asks": side(Side::Sell) }),
+        ))
+    }
+
+    /// Every resting order of both sides, in priority order.
+    fn book_orders(&self) -> Response {
+        let side = |side| self.orders(side, |_| true);
+        Response::ok(json!({ "bids": side(Side::Buy), "asks": side(Side::Sell) }))
+    }
+
+    fn user_orders(&self, user_id: &str) -> Result<Response, Response> {
+        let user_id: u64 = user_id
+            .parse()
+            .map_err(|e| Response::error(400, format!("user {}", e)))?;
+        let mut orders = self.orders(Side::Buy, |id| id == user_id);
+        orders.extend(self.orders(Side::Sell, |id| id == user_id));
+        Ok(Response::ok(json!({ "orders": orders })))
+    }
+
+    fn orders(&self, side: Side, user: impl Fn(u64) -> bool) -> Vec<Value> {
+        let instrument = self.matcher.instrument();
+        self.matcher
+            .book()
+            .orders(side)
+            .filter(|o| user(o.user_id()))
+            .map(|o| json!(OrderReport::new(o, instrument)))
+            .collect()
+    }
+
+    /// The most recent trades, oldest first, at most `limit` of them.
+    fn recent_trades(&self, request: &Request) -> Result<Response, Response> {
+        let limit = request.param("limit")?.unwrap_or(100);
+        let skip = self.trades.len().saturating_sub(limit);
+        let trades: Vec<&Value> = self.trades.iter().skip(skip).collect();
+        Ok(Response::ok(json!({ "trades": trades })))
+    }
+}
+
+/// An event or trade as a record of the JSONL output format.
+fn record(instrument: Instrument, report: Report) -> Value {
+    let mut writer = EventWriter::new(Vec::new(), Format::Jsonl, instrument);
+    let written = match report {
+        Report::Event(event) => writer.write_event(event),
+        Report::Trade(trade) => writer.write_trade(trade),
+    };
+    let line = written
+        .and_then(|()| writer.into_inner())
+        .expect("writing to memory cannot fail");
+    serde_json::from_slice(&line).expect("JSONL records are JSON")
+}
+
+fn accept(listener: TcpListener, sender: Sender<Call>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &sender) {
+                eprintln!("Connection: {}", e);
+            }
+        });
+    }
+}
+
+/// Answers the request of one connection and closes it.
+fn serve(mut stream: TcpStream, sender: &Sender<Call>) -> Result<(), Box<dyn Error>> {
+    let response = match read_request(BufReader::new(stream.try_clone()?)) {
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            sender.send(Call { request, reply })?;
+            response.recv()?
+        }
+        Err(response) => response,
+    };
+    let body = response.body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason_phrase(response.status),
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads a request: its request line, the headers, of which only
+/// Content-Length matters, and the body.
+fn read_request(mut reader: impl BufRead) -> Result<Request, Response> {
+    let bad_request = |e: &dyn std::fmt::Display| Response::error(400, e);
+    let mut head = Vec::new();
+    let mut read_line = |reader: &mut dyn BufRead| -> Result<String, Response> {
+        let mut line = String::new();
+        let limit = (MAX_HEAD - head.len()) as u64;
+        Read::take(&mut *reader, limit)
+            .read_line(&mut line)
+            .map_err(|e| bad_request(&e))?;
+        if !line.ends_with('\n') {
+            return Err(Response::error(400, "request head too long or cut short"));
+        }
+        head.extend_from_slice(line.as_bytes());
+        Ok(line.trim_end().to_string())
+    };
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Response::error(400, "malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Response::error(400, "only HTTP/1.x is supported"));
+    }
+    let mut content_length = 0;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Response::error(400, "malformed header"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|e| bad_request(&e))?;
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(Response::error(413, "body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|e| bad_request(&e))?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        body,
+    })
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,408 @@
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::amend::Amend;
+use crate::cancel::MassCancel;
+use crate::command::Command;
+use crate::instrument::Instrument;
+use crate::notional::Notional;
+use crate::order::{Order, OrderType, Side};
+
+/// Records that are not orders but instructions to the engine.
+#[derive(Debug, Copy, Clone, Deserialize)]
+pub enum Control {
+    MassCancel,
+    OpenSession,
+    CloseSession,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RecordType {
+    Order(OrderType),
+    Control(Control),
+}
+
+/// A record of the order schema (version 1) of CSV input, which the other
+/// inputs fill in too so that their orders are checked the same way.
+#[derive(Debug, Deserialize)]
+pub struct OrderBuilder {
+    pub order_type: RecordType,
+    pub side: Option<Side>,
+    pub price: String,
+    pub initial_qty: String,
+    pub user_id: u64,
+    #[serde(default)]
+    pub session_id: Option<u64>,
+    #[serde(default)]
+    pub cancel_on_disconnect: Option<bool>,
+}
+
+/// Actions of the command schema (version 2) of CSV input.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    New,
+    Cancel,
+    Amend,
+    MassCancel,
+    OpenSession,
+    CloseSession,
+}
+
+/// A record of the command schema, recognized by its `action` column.
+/// Columns an action does not use are left empty; the record is turned
+/// into the matching `JsonCommand`, so both inputs accept the same commands.
+#[derive(Debug, Deserialize)]
+pub struct CommandRecord {
+    pub action: Action,
+    #[serde(default)]
+    pub order_id: Option<Uuid>,
+    #[serde(default)]
+    pub order_type: Option<OrderType>,
+    #[serde(default)]
+    pub side: Option<Side>,
+    #[serde(default)]
+    pub price: String,
+    #[serde(default)]
+    pub qty: String,
+    #[serde(default)]
+    pub user_id: Option<u64>,
+    #[serde(default)]
+    pub session_id: Option<u64>,
+    #[serde(default)]
+    pub cancel_on_disconnect: Option<bool>,
+}
+
+impl CommandRecord {
+    pub fn into_command(self) -> Result<JsonCommand, String> {
+        let required =
+            |field: &str| format!("{} is required for a {:?} record", field, self.action);
+        let decimal = |s: &str| (!s.trim().is_empty()).then(|| DecimalText(s.to_string()));
+        let user_id = self.user_id.ok_or_else(|| required("user_id"));
+        let order_id = self.order_id.ok_or_else(|| required("order_id"));
+        let session_id = self.session_id.ok_or_else(|| required("session_id"));
+        Ok(match self.action {
+            Action::New => JsonCommand::New {
+                order_type: self.order_type.ok_or_else(|| required("order_type"))?,
+                side: self.side.ok_or_else(|| required("side"))?,
+                price: DecimalText(self.price),
+                initial_qty: DecimalText(self.qty),
+                user_id: user_id?,
+                session_id: self.session_id,
+                cancel_on_disconnect: self.cancel_on_disconnect,
+            },
+            Action::Cancel => JsonCommand::Cancel {
+                order_id: order_id?,
+                user_id: user_id?,
+            },
+            Action::Amend => JsonCommand::Amend {
+                order_id: order_id?,
+                user_id: user_id?,
+                price: decimal(&self.price),
+                qty: decimal(&self.qty),
+            },
+            Action::MassCancel => {
+                let (min, max) = self
+                    .price
+                    .split_once('-')
+                    .unwrap_or((&self.price, &self.price));
+                JsonCommand::MassCancel {
+                    user_id: user_id?,
+                    side: self.side,
+                    min_price: decimal(min),
+                    max_price: decimal(max),
+                }
+            }
+            Action::OpenSession => JsonCommand::OpenSession {
+                session_id: session_id?,
+                user_id: user_id?,
+            },
+            Action::CloseSession => JsonCommand::CloseSession {
+                session_id: session_id?,
+            },
+        })
+    }
+}
+
+/// A decimal price or quantity of a JSON command. Decimals must be strings,
+/// since reading them as JSON numbers may lose precision; integers are
+/// exact and taken either way.
+#[derive(Debug, Clone)]
+pub struct DecimalText(pub String);
+
+impl<'de> Deserialize<'de> for DecimalText {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<DecimalText, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = DecimalText;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a decimal string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<DecimalText, E> {
+                Ok(DecimalText(v.to_string()))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<DecimalText, E> {
+                Ok(DecimalText(v.to_string()))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<DecimalText, E> {
+                Ok(DecimalText(v.to_string()))
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<DecimalText, E> {
+                Err(E::custom(format!(
+                    "number {} must be given as a string to keep its precision",
+                    v
+                )))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// One line of JSON Lines input. New orders go through `OrderBuilder`, so
+/// they are checked exactly like CSV records.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum JsonCommand {
+    New {
+        order_type: OrderType,
+        side: Side,
+        price: DecimalText,
+        initial_qty: DecimalText,
+        user_id: u64,
+        #[serde(default)]
+        session_id: Option<u64>,
+        #[serde(default)]
+        cancel_on_disconnect: Option<bool>,
+    },
+    Cancel {
+        order_id: Uuid,
+        user_id: u64,
+    },
+    Amend {
+        order_id: Uuid,
+        user_id: u64,
+        #[serde(default)]
+        price: Option<DecimalText>,
+        #[serde(default)]
+        qty: Option<DecimalText>,
+    },
+    MassCancel {
+        user_id: u64,
+        #[serde(default)]
+        side: Option<Side>,
+        #[serde(default)]
+        min_price: Option<DecimalText>,
+        #[serde(default)]
+        max_price: Option<DecimalText>,
+    },
+    OpenSession {
+        session_id: u64,
+        user_id: u64,
+    },
+    CloseSession {
+        session_id: u64,
+    },
+}
+
+impl JsonCommand {
+    pub fn build(self, instrument: &Instrument) -> Result<Command, String> {
+        match self {
+            JsonCommand::New {
+                order_type,
+                side,
+                price,
+                initial_qty,
+                user_id,
+                session_id,
+                cancel_on_disconnect,
+            } => OrderBuilder {
+                order_type: RecordType::Order(order_type),
+                side: Some(side),
+                price: price.0,
+                initial_qty: initial_qty.0,
+                user_id,
+                session_id,
+                cancel_on_disconnect,
+            }
+            .build(instrument),
+            JsonCommand::Cancel { order_id, user_id } => Ok(Command::Cancel { order_id, user_id }),
+            JsonCommand::Amend {
+                order_id,
+                user_id,
+                price,
+                qty,
+            } => Ok(Command::Amend(Amend {
+                order_id,
+                user_id,
+                price: price
+                    .map(|p| parse_price(instrument, "price", &p.0))
+                    .transpose()?,
+                qty: qty
+                    .map(|q| parse_qty(instrument, "qty", &q.0))
+                    .transpose()?,
+            })),
+            JsonCommand::MassCancel {
+                user_id,
+                side,
+                min_price,
+                max_price,
+            } => {
+                let bound = |p: Option<DecimalText>, field| {
+                    p.map(|p| parse_price(instrument, field, &p.0)).transpose()
+                };
+                Ok(Command::MassCancel(MassCancel {
+                    user_id,
+                    side,
+                    min_price: bound(min_price, "min_price")?,
+                    max_price: bound(max_price, "max_price")?,
+                }))
+            }
+            JsonCommand::OpenSession {
+                session_id,
+                user_id,
+            } => Ok(Command::OpenSession {
+                session_id,
+                user_id,
+            }),
+            JsonCommand::CloseSession { session_id } => Ok(Command::CloseSession { session_id }),
+        }
+    }
+}
+
+pub fn parse_price(instrument: &Instrument, field: &str, s: &str) -> Result<u64, String> {
+    instrument
+        .parse_price(s)
+        .map_err(|e| format!("{} {}", field, e))
+}
+
+pub fn parse_qty(instrument: &Instrument, field: &str, s: &str) -> Result<u64, String> {
+    instrument
+        .parse_qty(s)
+        .map_err(|e| format!("{} {}", field, e))
+}
+
+impl OrderBuilder {
+    pub fn build(&self, instrument: &Instrument) -> Result<Command, String> {
+        match self.order_type {
+            RecordType::Order(order_type) => self
+                .build_order(order_type, instrument)
+                .map(Command::NewOrder),
+            RecordType::Control(Control::MassCancel) => {
+                self.build_mass_cancel(instrument).map(Command::MassCancel)
+            }
+            RecordType::Control(Control::OpenSession) => Ok(Command::OpenSession {
+                session_id: self.session_id()?,
+                user_id: self.user_id,
+            }),
+            RecordType::Control(Control::CloseSession) => Ok(Command::CloseSession {
+                session_id: self.session_id()?,
+            }),
+        }
+    }
+
+    fn session_id(&self) -> Result<u64, String> {
+        self.session_id
+            .ok_or_else(|| "session_id is required for a session record".to_string())
+    }
+
+    /// Converts the decimal price and quantity into the fixed-point integers
+    /// of the instrument.
+    fn build_order(&self, order_type: OrderType, instrument: &Instrument) -> Result<Order, String> {
+        let side = self.side.ok_or("side is required for an order")?;
+        let price = parse_price(instrument, "price", &self.price)?;
+        let initial_qty = parse_qty(instrument, "initial_qty", &self.initial_qty)?;
+        if Notional::new(price, initial_qty).to_u64().is_none() {
+            return Err(format!(
+                "price {} multiplied by initial_qty {} overflows the notional range",
+                self.price, self.initial_qty
+            ));
+        }
+        let order = Order::new(order_type, side, price, initial_qty, self.user_id);
+        Ok(match self.session_id {
+            Some(session_id) => {
+                order.with_session(session_id, self.cancel_on_disconnect.unwrap_or(false))
+            }
+            None => order,
+        })
+    }
+
+    /// The price column of a mass cancel holds an optional range: `lo-hi`,
+    /// `lo-`, `-hi`, a single price, or nothing for all prices.
+    fn build_mass_cancel(&self, instrument: &Instrument) -> Result<MassCancel, String> {
+        let parse = |s: &str| -> Result<Option<u64>, String> {
+            if s.trim().is_empty() {
+                return Ok(None);
+            }
+            parse_price(instrument, "price", s).map(Some)
+        };
+        let (min_price, max_price) = match self.price.split_once('-') {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => {
+                let price = parse(&self.price)?;
+                (price, price)
+            }
+        };
+        Ok(MassCancel {
+            user_id: self.user_id,
+            side: self.side,
+            min_price,
+            max_price,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(line: &str) -> Result<Command, String> {
+        serde_json::from_str::<JsonCommand>(line)
+            .map_err(|e| e.to_string())
+            .and_then(|command| command.build(&Instrument::default()))
+    }
+
+    #[test]
+    fn test_mass_cancel_bounds() {
+        let command = build(r#"{"action":"mass_cancel","user_id":1,"max_price":"100"}"#);
+        assert!(matches!(
+            command,
+            Ok(Command::MassCancel(MassCancel {
+                user_id: 1,
+                side: None,
+                min_price: None,
+                max_price: Some(100),
+            }))
+        ));
+        assert!(
+            build(r#"{"action":"mass_cancel","user_id":1,"min_price":"x"}"#)
+                .unwrap_err()
+                .starts_with("min_price")
+        );
+    }
+
+    #[test]
+    fn test_decimals_must_be_strings() {
+        let line = r#"{"action":"amend","order_id":"00000000-0000-0000-0000-000000000001","user_id":1,"price":100.5}"#;
+        let error = build(line).unwrap_err();
+        assert!(
+            error.contains("number 100.5 must be given as a string"),
+            "{}",
+            error
+        );
+
+        // Integers are exact
+        let line = r#"{"action":"amend","order_id":"00000000-0000-0000-0000-000000000001","user_id":1,"qty":5}"#;
+        assert!(matches!(
+            build(line),
+            Ok(Command::Amend(Amend { qty: Some(5), .. }))
+        ));
+    }
+}
@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+use crate::allocation::Allocation;
+use crate::error::DecimalError;
+
+/// Largest number of decimal places a scale may have, so that `10^scale`
+/// still fits into `u64`.
+pub const MAX_SCALE: u32 = 18;
+
+/// What to do with digits beyond the scale of an instrument.
+#[derive(
+    Display, EnumString, Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Rounding {
+    /// Refuse values that cannot be represented exactly.
+    #[default]
+    Reject,
+    /// Drop the extra digits.
+    Down,
+    /// Round to the nearest representable value, halves away from zero.
+    HalfUp,
+}
+
+/// Describes how prices and quantities of the traded instrument map onto the
+/// integers the matcher works with. A scale is the number of decimal places,
+/// so with a price scale of 2 the string `100.25` becomes `10025`. The
+/// instrument also decides how fills are allocated within a price level.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Instrument {
+    price_scale: u32,
+    qty_scale: u32,
+    rounding: Rounding,
+    allocation: Allocation,
+}
+
+impl Instrument {
+    pub fn new(price_scale: u32, qty_scale: u32) -> Result<Instrument, DecimalError> {
+        for scale in [price_scale, qty_scale] {
+            if scale > MAX_SCALE {
+                return Err(DecimalError::ScaleTooLarge(scale));
+            }
+        }
+        Ok(Instrument {
+            price_scale,
+            qty_scale,
+            rounding: Rounding::default(),
+            allocation: Allocation::default(),
+        })
+    }
+
+    pub fn with_rounding(mut self, rounding: Rounding) -> Instrument {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn with_allocation(mut self, allocation: Allocation) -> Instrument {
+        self.allocation = allocation;
+        self
+    }
+
+    pub fn price_scale(&self) -> u32 {
+        self.price_scale
+    }
+
+    pub fn qty_scale(&self) -> u32 {
+        self.qty_scale
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    pub fn allocation(&self) -> Allocation {
+        self.allocation
+    }
+
+    pub fn parse_price(&self, s: &str) -> Result<u64, DecimalError> {
+        parse_fixed(s, self.price_scale, self.rounding)
+    }
+
+    pub fn parse_qty(&self, s: &str) -> Result<u64, DecimalError> {
+        parse_fixed(s, self.qty_scale, self.rounding)
+    }
+
+    pub fn format_price(&self, price: u64) -> String {
+        format_fixed(price, self.price_scale)
+    }
+
+    pub fn format_qty(&self, qty: u64) -> String {
+        format_fixed(qty, self.qty_scale)
+    }
+}
+
+/// Parses a non-negative decimal string into an integer number of
+/// `10^-scale` units.
+pub fn parse_fixed(s: &str, scale: u32, rounding: Rounding) -> Result<u64, DecimalError> {
+    let invalid = || DecimalError::Invalid(s.to_string());
+    let overflow = || DecimalError::Overflow(s.to_string());
+
+    let trimmed = s.trim();
+    let (int_part, frac_part) = match trimmed.split_once('.') {
+        Some((int_part, frac_part)) => (int_part, frac_part),
+        None => (trimmed, ""),
+    };
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err(invalid());
+    }
+    if !int_part
+        .bytes()
+        .chain(frac_part.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let mut value: u64 = 0;
+    for digit in int_part.bytes() {
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(u64::from(digit - b'0')))
+            .ok_or_else(overflow)?;
+    }
+
+    let (kept, dropped) = frac_part.split_at(frac_part.len().min(scale as usize));
+    let mut frac_digits = kept.bytes();
+    for _ in 0..scale {
+        let digit = frac_digits.next().map_or(0, |d| d - b'0');
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(u64::from(digit)))
+            .ok_or_else(overflow)?;
+    }
+
+    if dropped.bytes().any(|d| d != b'0') {
+        match rounding {
+            Rounding::Reject => {
+                return Err(DecimalError::TooPrecise {
+                    value: s.to_string(),
+                    scale,
+                })
+            }
+            Rounding::Down => {}
+            Rounding::HalfUp => {
+                if dropped.as_bytes()[0] >= b'5' {
+                    value = value.checked_add(1).ok_or_else(overflow)?;
+                }
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// Formats an integer number of `10^-scale` units as a decimal string with
+/// exactly `scale` fractional digits.
+pub fn format_fixed(value: u64, scale: u32) -> String {
+    if scale == 0 {
+        return value.to_string();
+    }
+    let unit = 10u64.pow(scale);
+    format!(
+        "{}.{:0width$}",
+        value / unit,
+        value % unit,
+        width = scale as usize
+    )
+}
+
+/// Like `format_fixed`, for signed amounts such as profits and losses.
+pub fn format_fixed_signed(value: i128, scale: u32) -> String {
+    let unit = 10u128.pow(scale);
+    let magnitude = value.unsigned_abs();
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        return format!("{}{}", sign, magnitude);
+    }
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        magnitude / unit,
+        magnitude % unit,
+        width = scale as usize
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exact() {
+        assert_eq!(parse_fixed("100", 0, Rounding::Reject), Ok(100));
+        assert_eq!(parse_fixed("100.25", 2, Rounding::Reject), Ok(10025));
+        assert_eq!(parse_fixed("100.2", 2, Rounding::Reject), Ok(10020));
+        assert_eq!(parse_fixed("100", 2, Rounding::Reject), Ok(10000));
+        assert_eq!(parse_fixed(".5", 1, Rounding::Reject), Ok(5));
+        assert_eq!(parse_fixed("7.", 1, Rounding::Reject), Ok(70));
+        assert_eq!(parse_fixed("1.2500", 2, Rounding::Reject), Ok(125));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in ["", ".", "-1", "1.2.3", "abc", "1e5"] {
+            assert_eq!(
+                parse_fixed(s, 2, Rounding::Reject),
+                Err(DecimalError::Invalid(s.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_over_precision() {
+        assert_eq!(
+            parse_fixed("100.255", 2, Rounding::Reject),
+            Err(DecimalError::TooPrecise {
+                value: "100.255".to_string(),
+                scale: 2
+            })
+        );
+        assert_eq!(parse_fixed("100.255", 2, Rounding::Down), Ok(10025));
+        assert_eq!(parse_fixed("100.255", 2, Rounding::HalfUp), Ok(10026));
+        assert_eq!(parse_fixed("100.254", 2, Rounding::HalfUp), Ok(10025));
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        assert_eq!(
+            parse_fixed("18446744073709551615", 0, Rounding::Reject),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            parse_fixed("18446744073709551616", 0, Rounding::Reject),
+            Err(DecimalError::Overflow("18446744073709551616".to_string()))
+        );
+        assert_eq!(
+            parse_fixed("18446744073709551615", 1, Rounding::Reject),
+            Err(DecimalError::Overflow("18446744073709551615".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_fixed(100, 0), "100");
+        assert_eq!(format_fixed(10025, 2), "100.25");
+        assert_eq!(format_fixed(5, 3), "0.005");
+        assert_eq!(format_fixed(10000, 2), "100.00");
+        assert_eq!(format_fixed_signed(-5, 3), "-0.005");
+        assert_eq!(format_fixed_signed(10025, 2), "100.25");
+        assert_eq!(format_fixed_signed(-42, 0), "-42");
+    }
+
+    #[test]
+    fn test_instrument_scale_limit() {
+        assert!(Instrument::new(MAX_SCALE, 0).is_ok());
+        assert_eq!(
+            Instrument::new(0, MAX_SCALE + 1),
+            Err(DecimalError::ScaleTooLarge(MAX_SCALE + 1))
+        );
+    }
+}
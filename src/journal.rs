@@ -0,0 +1,413 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::command::Command;
+use crate::error::{JournalError, MatchError};
+use crate::Matcher;
+
+/// Size of the fixed part of a record: payload length, checksum and
+/// sequence number.
+const HEADER_LEN: usize = 4 + 4 + 8;
+
+/// A command together with its position in the journal.
+#[derive(Debug, Clone)]
+pub struct JournalRecord {
+    pub seq: u64,
+    pub command: Command,
+}
+
+/// Append-only log of the commands given to a matcher. Each record is laid
+/// out as
+///
+/// ```text
+/// payload length: u32 LE | crc32 of seq and payload: u32 LE | seq: u64 LE | payload
+/// ```
+///
+/// where the payload is the JSON encoded command. Every record is written
+/// with a single write call and synced to disk before the command it holds
+/// is reported, so it survives a crash of the process or of the machine.
+pub struct Journal {
+    file: File,
+    next_seq: u64,
+}
+
+impl Journal {
+    /// Opens or creates a journal and reads back its records. A record cut
+    /// short by a crash at the end of the file is dropped and the file is
+    /// truncated, any other damage is reported as corruption.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(Journal, Vec<JournalRecord>), JournalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let (records, valid_len) = parse_records(&data)?;
+        if valid_len < data.len() {
+            file.set_len(valid_len as u64)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let next_seq = records.last().map_or(1, |r| r.seq + 1);
+        Ok((Journal { file, next_seq }, records))
+    }
+
+    /// Reads the records of a journal without opening it for writing. A
+    /// record cut short at the end is left out, as by `open`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<JournalRecord>, JournalError> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Ok(parse_records(&data)?.0)
+    }
+
+    /// Sequence number the next appended command gets.
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Writes a command to the end of the journal and returns its sequence
+    /// number.
+    pub fn append(&mut self, command: &Command) -> Result<u64, JournalError> {
+        let seq = self.next_seq;
+        let payload =
+            serde_json::to_vec(command).map_err(|e| JournalError::Format(e.to_string()))?;
+        let len = u32::try_from(payload.len())
+            .map_err(|_| JournalError::Format("command is too large".to_string()))?;
+
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&checksum(seq, &payload).to_le_bytes());
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(&payload);
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        self.next_seq += 1;
+        Ok(seq)
+    }
+
+    /// Replays into `matcher` the records it has not seen yet, that is the
+    /// ones after its sequence number, e.g. on top of a snapshot. Events and
+    /// trades produced by the replay were already reported before the
+    /// restart, so they are discarded.
+    pub fn replay(
+        &mut self,
+        matcher: &mut Matcher,
+        records: Vec<JournalRecord>,
+    ) -> Result<(), JournalError> {
+        for record in records {
+            match matcher.apply_record(record) {
+                Err(e) if !e.is_refusal() => return Err(e.into()),
+                _ => {}
+            }
+            matcher.drain_events();
+            matcher.drain_trades();
+        }
+        // A snapshot may be newer than a journal that was started afresh
+        self.next_seq = self.next_seq.max(matcher.sequence + 1);
+        Ok(())
+    }
+}
+
+impl Matcher {
+    /// Applies a journaled command the matcher has not seen yet, leaving its
+    /// events and trades to the caller. Records at or before the sequence
+    /// number of the matcher are skipped.
+    pub fn apply_record(&mut self, record: JournalRecord) -> Result<(), MatchError> {
+        if record.seq <= self.sequence {
+            return Ok(());
+        }
+        self.sequence = record.seq;
+        self.execute(record.command)
+    }
+
+    /// Applies the command and writes it to the journal before its events
+    /// are reported. A command failing with anything but a refusal is left
+    /// out: replaying it would fail again, and the journal could no longer
+    /// be loaded.
+    pub fn execute_journaled(
+        &mut self,
+        journal: &mut Journal,
+        command: Command,
+    ) -> Result<(), JournalError> {
+        let result = self.execute(command.clone());
+        if let Err(e) = &result {
+            if !e.is_refusal() {
+                return Err(e.clone().into());
+            }
+        }
+        self.sequence = journal.append(&command)?;
+        result.map_err(JournalError::from)
+    }
+}
+
+/// Returns the complete records of `data` and the number of bytes they take.
+fn parse_records(data: &[u8]) -> Result<(Vec<JournalRecord>, usize), JournalError> {
+    let mut records: Vec<JournalRecord> = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        if rest.len() < HEADER_LEN {
+            break;
+        }
+        let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+        let seq = u64::from_le_bytes(rest[8..16].try_into().unwrap());
+        let expected = records.last().map_or(seq, |r| r.seq + 1);
+        let Some(payload) = rest[HEADER_LEN..].get(..len) else {
+            // A record cut short by a crash is the last one written, so no
+            // record can follow it. A damaged length running past the end
+            // of the file would otherwise hide the records after it.
+            if seq != expected || holds_record(&rest[HEADER_LEN..], seq + 1) {
+                return Err(JournalError::Corrupt { offset });
+            }
+            break;
+        };
+        let end = offset + HEADER_LEN + len;
+        if checksum(seq, payload) != crc {
+            if end == data.len() {
+                break;
+            }
+            return Err(JournalError::Corrupt { offset });
+        }
+        if seq != expected {
+            return Err(JournalError::Corrupt { offset });
+        }
+        let command =
+            serde_json::from_slice(payload).map_err(|e| JournalError::Format(e.to_string()))?;
+        records.push(JournalRecord { seq, command });
+        offset = end;
+    }
+    Ok((records, offset))
+}
+
+/// Whether a complete record numbered `seq` starts anywhere in `data`.
+fn holds_record(data: &[u8], seq: u64) -> bool {
+    (0..data.len()).any(|start| {
+        let rest = &data[start..];
+        if rest.len() < HEADER_LEN || rest[8..16] != seq.to_le_bytes() {
+            return false;
+        }
+        let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+        rest[HEADER_LEN..]
+            .get(..len)
+            .is_some_and(|payload| checksum(seq, payload) == crc)
+    })
+}
+
+fn checksum(seq: u64, payload: &[u8]) -> u32 {
+    let crc = crc32_update(!0, &seq.to_le_bytes());
+    !crc32_update(crc, payload)
+}
+
+/// Bitwise CRC-32 (IEEE 802.3) without the final inversion.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::order::{Order, OrderType, Side};
+    use tempfile::NamedTempFile;
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::NewOrder(Order::new(OrderType::Lim, Side::Buy, 100, 10, 1)),
+            Command::NewOrder(Order::new(OrderType::Lim, Side::Buy, 101, 5, 2)),
+            Command::NewOrder(Order::new(OrderType::Lim, Side::Sell, 0, 5, 2)),
+            Command::NewOrder(Order::new(OrderType::Lim, Side::Sell, 103, 7, 3)),
+            Command::NewOrder(Order::new(OrderType::Ioc, Side::Sell, 100, 8, 4)),
+            Command::NewOrder(Order::new(OrderType::Lim, Side::Buy, 104, 2, 5)),
+        ]
+    }
+
+    fn state(matcher: &Matcher) -> Vec<u8> {
+        let mut saved = Vec::new();
+        matcher.save_snapshot(&mut saved).unwrap();
+        saved
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_recovery_from_snapshot_and_tail() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, records) = Journal::open(journal_file.path()).unwrap();
+        assert!(records.is_empty());
+
+        let mut matcher = Matcher::new();
+        let mut snapshot = None;
+        for (i, command) in commands().into_iter().enumerate() {
+            let _ = matcher.execute_journaled(&mut journal, command);
+            if i == 2 {
+                snapshot = Some(matcher.snapshot());
+            }
+        }
+        assert_eq!(matcher.sequence(), 6);
+
+        let (mut journal, records) = Journal::open(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(journal.next_seq(), 7);
+
+        let mut recovered = Matcher::from_snapshot(snapshot.unwrap()).unwrap();
+        journal.replay(&mut recovered, records).unwrap();
+        assert_eq!(state(&recovered), state(&matcher));
+        assert_eq!(recovered.drain_events().count(), 0);
+
+        // Recovering from the journal alone gives the same state
+        let (mut journal, records) = Journal::open(journal_file.path()).unwrap();
+        let mut from_scratch = Matcher::new();
+        journal.replay(&mut from_scratch, records).unwrap();
+        assert_eq!(state(&from_scratch), state(&matcher));
+    }
+
+    #[test]
+    fn test_read_and_apply_records() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, _) = Journal::open(journal_file.path()).unwrap();
+        let mut matcher = Matcher::new();
+        let mut events = 0;
+        for command in commands() {
+            let _ = matcher.execute_journaled(&mut journal, command);
+            events += matcher.drain_events().count();
+        }
+
+        let records = Journal::read(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 6);
+        let mut replayed = Matcher::new();
+        for record in records.iter().cloned().chain(records.iter().cloned()) {
+            let _ = replayed.apply_record(record);
+        }
+        // Records already applied are skipped the second time around
+        assert_eq!(replayed.sequence(), 6);
+        assert_eq!(replayed.drain_events().count(), events);
+        assert_eq!(state(&replayed), state(&matcher));
+    }
+
+    #[test]
+    fn test_failed_command_is_not_journaled() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, _) = Journal::open(journal_file.path()).unwrap();
+        let mut matcher = Matcher::new().with_accounts(Accounts::new());
+        let deposit = Command::Deposit {
+            user_id: 1,
+            base: u64::MAX,
+            quote: 0,
+        };
+        matcher
+            .execute_journaled(&mut journal, deposit.clone())
+            .unwrap();
+        assert!(matches!(
+            matcher.execute_journaled(&mut journal, deposit),
+            Err(JournalError::Match(MatchError::BalanceOverflow(1)))
+        ));
+        assert_eq!(matcher.sequence(), 1);
+        let sell = Order::new(OrderType::Lim, Side::Sell, 103, 7, 1);
+        matcher
+            .execute_journaled(&mut journal, Command::NewOrder(sell))
+            .unwrap();
+
+        let (mut journal, records) = Journal::open(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        let mut replayed = Matcher::new().with_accounts(Accounts::new());
+        journal.replay(&mut replayed, records).unwrap();
+        assert_eq!(state(&replayed), state(&matcher));
+    }
+
+    #[test]
+    fn test_torn_record_is_truncated() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, _) = Journal::open(journal_file.path()).unwrap();
+        for command in commands() {
+            journal.append(&command).unwrap();
+        }
+        drop(journal);
+
+        let full_len = std::fs::metadata(journal_file.path()).unwrap().len();
+        let file = OpenOptions::new()
+            .write(true)
+            .open(journal_file.path())
+            .unwrap();
+        file.set_len(full_len - 3).unwrap();
+
+        let (mut journal, records) = Journal::open(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(journal.next_seq(), 6);
+        let truncated_len = std::fs::metadata(journal_file.path()).unwrap().len();
+        assert!(truncated_len < full_len - 3);
+
+        // Appending continues right after the last complete record
+        journal.append(&commands()[5]).unwrap();
+        let (_, records) = Journal::open(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[5].seq, 6);
+    }
+
+    #[test]
+    fn test_damaged_record_is_corruption() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, _) = Journal::open(journal_file.path()).unwrap();
+        for command in commands() {
+            journal.append(&command).unwrap();
+        }
+        drop(journal);
+
+        let mut data = std::fs::read(journal_file.path()).unwrap();
+        data[HEADER_LEN + 2] ^= 0xFF;
+        std::fs::write(journal_file.path(), &data).unwrap();
+
+        assert!(matches!(
+            Journal::open(journal_file.path()),
+            Err(JournalError::Corrupt { offset: 0 })
+        ));
+
+        // The same damage in the last record looks like a torn write
+        let mut data = std::fs::read(journal_file.path()).unwrap();
+        data[HEADER_LEN + 2] ^= 0xFF;
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        std::fs::write(journal_file.path(), &data).unwrap();
+        let (_, records) = Journal::open(journal_file.path()).unwrap();
+        assert_eq!(records.len(), 5);
+    }
+
+    #[test]
+    fn test_damaged_length_is_corruption() {
+        let journal_file = NamedTempFile::new().unwrap();
+        let (mut journal, _) = Journal::open(journal_file.path()).unwrap();
+        for command in commands() {
+            journal.append(&command).unwrap();
+        }
+        drop(journal);
+
+        // The length of the second record now runs past the end of the file
+        let mut data = std::fs::read(journal_file.path()).unwrap();
+        let first_len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        let second = HEADER_LEN + first_len;
+        data[second + 2] = 0x7F;
+        std::fs::write(journal_file.path(), &data).unwrap();
+
+        assert!(matches!(
+            Journal::open(journal_file.path()),
+            Err(JournalError::Corrupt { offset }) if offset == second
+        ));
+        assert_eq!(std::fs::read(journal_file.path()).unwrap(), data);
+    }
+}
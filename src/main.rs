@@ -1,60 +1,961 @@
 extern crate csv;
 extern crate matcher;
 
-use clap::{Arg, ArgAction, Command};
-use csv::Reader;
-use serde::Deserialize;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use csv::{Reader, ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use matcher::accounts::Accounts;
+use matcher::allocation::Allocation;
+use matcher::error::{DecimalError, JournalError, MatchError};
+use matcher::event::Event;
+use matcher::fee::{FeeRate, FeeSchedule, FeeTier};
+use matcher::input::{CommandRecord, JsonCommand, OrderBuilder};
+use matcher::instrument::{
+    format_fixed, format_fixed_signed, parse_fixed, Instrument, Rounding, MAX_SCALE,
+};
+use matcher::journal::Journal;
+use matcher::order::Side;
+use matcher::order::{IdGenerator, Order};
+use matcher::output::{EventWriter, Format};
+use matcher::position::Mark;
+use matcher::trade::Trade;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Stdout};
 use std::path::PathBuf;
 
+mod fix_gateway;
+mod gateway;
+mod gen;
+mod http;
+mod repl;
+mod stats;
+
+/// Opening balance of a user, as read from the `--balances` file.
 #[derive(Debug, Deserialize)]
-struct OrderBuilder {
-    order_type: matcher::order::OrderType,
-    side: matcher::order::Side,
-    price: u64,
-    initial_qty: u64,
+struct BalanceRecord {
     user_id: u64,
+    base: String,
+    quote: String,
 }
 
-fn process_csv(path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let mut matcher = matcher::Matcher::new();
+/// End-of-run balance of a user, as written to the `--balances-out` file.
+#[derive(Debug, Serialize)]
+struct BalanceReport {
+    user_id: u64,
+    base_free: String,
+    base_locked: String,
+    quote_free: String,
+    quote_locked: String,
+}
+
+/// Quote amounts are notionals, so they carry the decimal places of both
+/// the price and the quantity.
+fn quote_scale(instrument: &Instrument) -> Result<u32, DecimalError> {
+    let scale = instrument.price_scale() + instrument.qty_scale();
+    if scale > MAX_SCALE {
+        return Err(DecimalError::ScaleTooLarge(scale));
+    }
+    Ok(scale)
+}
+
+fn read_balances(path: &str, instrument: &Instrument) -> Result<Accounts, Box<dyn Error>> {
+    let scale = quote_scale(instrument)?;
+    let mut accounts = Accounts::new();
     let mut reader = Reader::from_path(path)?;
+    for (index, result) in reader.deserialize::<BalanceRecord>().enumerate() {
+        let parsed = result.map_err(|e| e.to_string()).and_then(|r| {
+            let base = instrument
+                .parse_qty(&r.base)
+                .map_err(|e| format!("base {}", e))?;
+            let quote = parse_fixed(&r.quote, scale, instrument.rounding())
+                .map_err(|e| format!("quote {}", e))?;
+            accounts
+                .deposit(r.user_id, base, quote)
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = parsed {
+            eprintln!("Error at balance record {}: {}", index + 1, e);
+            return Err(e.into());
+        }
+    }
+    Ok(accounts)
+}
+
+fn write_balances(path: &str, matcher: &matcher::Matcher) -> Result<(), Box<dyn Error>> {
+    let instrument = matcher.instrument();
+    let scale = quote_scale(instrument)?;
+    let mut writer = Writer::from_path(path)?;
+    for (user_id, account) in matcher.accounts().into_iter().flat_map(Accounts::iter) {
+        writer.serialize(BalanceReport {
+            user_id,
+            base_free: instrument.format_qty(account.base.free),
+            base_locked: instrument.format_qty(account.base.locked),
+            quote_free: format_fixed(account.quote.free, scale),
+            quote_locked: format_fixed(account.quote.locked, scale),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Position of a user at the end of the run, as written to the
+/// `--positions-out` file. Average price and unrealized PnL are empty when
+/// there is nothing to value.
+#[derive(Debug, Serialize)]
+struct PositionReport {
+    user_id: u64,
+    position: String,
+    average_price: Option<String>,
+    realized_pnl: String,
+    unrealized_pnl: Option<String>,
+    fees: String,
+}
+
+fn write_positions(
+    path: &str,
+    matcher: &matcher::Matcher,
+    mark: Mark,
+) -> Result<(), Box<dyn Error>> {
+    let instrument = matcher.instrument();
+    let scale = quote_scale(instrument)?;
+    let mark_price = matcher.mark_price(mark);
+    let mut writer = Writer::from_path(path)?;
+    for (user_id, position) in matcher.positions().iter() {
+        writer.serialize(PositionReport {
+            user_id,
+            position: format_fixed_signed(position.qty, instrument.qty_scale()),
+            average_price: position
+                .average_price()
+                .map(|price| instrument.format_price(price)),
+            realized_pnl: format_fixed_signed(position.realized_pnl, scale),
+            unrealized_pnl: mark_price
+                .map(|price| format_fixed_signed(position.unrealized_pnl(price), scale)),
+            fees: format_fixed_signed(position.fees, scale),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A trade as written to the `--trades-out` file. Fees are in quote units,
+/// negative for rebates.
+#[derive(Debug, Serialize)]
+struct TradeReport {
+    taker_order_id: String,
+    maker_order_id: String,
+    taker_side: Side,
+    price: String,
+    qty: String,
+    taker_user_id: u64,
+    maker_user_id: u64,
+    taker_fee: String,
+    maker_fee: String,
+}
+
+/// Reads a fee rate given as `<n>bps` or as a signed decimal amount of quote
+/// per quantity unit.
+fn parse_fee_rate(s: &str, instrument: &Instrument) -> Result<FeeRate, String> {
+    let s = s.trim();
+    if s.ends_with("bps") {
+        return s.parse().map_err(|e| format!("fee {}: {}", s, e));
+    }
+    let (negative, magnitude) = match s.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, s),
+    };
+    let scale = quote_scale(instrument).map_err(|e| e.to_string())?;
+    let rate =
+        parse_fixed(magnitude, scale, instrument.rounding()).map_err(|e| format!("fee {}", e))?;
+    let rate = i64::try_from(rate).map_err(|_| format!("fee {} is out of range", s))?;
+    Ok(FeeRate::PerUnit(if negative { -rate } else { rate }))
+}
+
+#[derive(Debug, Deserialize)]
+struct FeeTierRecord {
+    user_id: u64,
+    maker: String,
+    taker: String,
+}
+
+fn read_fee_tiers(
+    path: &str,
+    mut fees: FeeSchedule,
+    instrument: &Instrument,
+) -> Result<FeeSchedule, Box<dyn Error>> {
+    let mut reader = Reader::from_path(path)?;
+    for (index, result) in reader.deserialize::<FeeTierRecord>().enumerate() {
+        let parsed = result.map_err(|e| e.to_string()).and_then(|r| {
+            let tier = FeeTier {
+                maker: parse_fee_rate(&r.maker, instrument)?,
+                taker: parse_fee_rate(&r.taker, instrument)?,
+            };
+            Ok((r.user_id, tier))
+        });
+        match parsed {
+            Ok((user_id, tier)) => fees = fees.with_user_tier(user_id, tier),
+            Err(e) => {
+                eprintln!("Error at fee tier record {}: {}", index + 1, e);
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(fees)
+}
+
+/// Where the events and trades of a run go.
+struct Output {
+    events: EventWriter<BufWriter<Stdout>>,
+    trades: Option<Writer<File>>,
+    /// Flush after every record, so a consumer at the other end of a pipe
+    /// sees each event as soon as it happens.
+    flush_each: bool,
+}
+
+impl Output {
+    /// Writes out everything the matcher produced since the last call.
+    fn write(&mut self, matcher: &mut matcher::Matcher) -> Result<(), Box<dyn Error>> {
+        let instrument = *matcher.instrument();
+        let events: Vec<Event> = matcher.drain_events().collect();
+        let trades: Vec<Trade> = matcher.drain_trades().collect();
+        self.events.write(&events, &trades)?;
+
+        if let Some(writer) = self.trades.as_mut() {
+            let scale = quote_scale(&instrument)?;
+            for trade in trades {
+                writer.serialize(TradeReport {
+                    taker_order_id: trade.taker_order_id.to_string(),
+                    maker_order_id: trade.maker_order_id.to_string(),
+                    taker_side: trade.taker_side,
+                    price: instrument.format_price(trade.price),
+                    qty: instrument.format_qty(trade.qty),
+                    taker_user_id: trade.taker_user_id,
+                    maker_user_id: trade.maker_user_id,
+                    taker_fee: format_fixed_signed(trade.taker_fee, scale),
+                    maker_fee: format_fixed_signed(trade.maker_fee, scale),
+                })?;
+            }
+        }
+        if self.flush_each {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.events.flush()?;
+        if let Some(writer) = self.trades.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Column order of files without a header row, for each schema version.
+const ORDER_COLUMNS: [&str; 7] = [
+    "order_type",
+    "side",
+    "price",
+    "initial_qty",
+    "user_id",
+    "session_id",
+    "cancel_on_disconnect",
+];
+const COMMAND_COLUMNS: [&str; 9] = [
+    "action",
+    "order_id",
+    "order_type",
+    "side",
+    "price",
+    "qty",
+    "user_id",
+    "session_id",
+    "cancel_on_disconnect",
+];
+
+/// How a CSV input file is laid out.
+struct CsvDialect {
+    /// Version of the records, taken from the columns when not given.
+    schema: Option<u32>,
+    delimiter: u8,
+    has_headers: bool,
+    /// Names of the columns in file order, in place of the header row.
+    /// Columns named `_`, or anything else the schema does not know, are
+    /// ignored.
+    columns: Option<Vec<String>>,
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!(
+            "delimiter must be a single ASCII character or `tab`, got `{}`",
+            s
+        )),
+    }
+}
+
+/// Applies the records of `input` one by one as they are read, so it works
+/// on a file as well as on a pipe that is still being written.
+///
+/// Version 1 records are the order records of `OrderBuilder`, version 2
+/// records the commands of `CommandRecord`. Unless given, the version is
+/// taken from the columns: an `action` column means version 2.
+fn process_csv<R: Read>(
+    input: R,
+    dialect: &CsvDialect,
+    matcher: &mut matcher::Matcher,
+    journal: Option<&mut Journal>,
+    output: &mut Output,
+    rejects: &mut Rejects,
+) -> Result<(), Box<dyn Error>> {
+    let instrument = *matcher.instrument();
+    let mut reader = ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .has_headers(dialect.has_headers)
+        .from_reader(input);
+    let header_row = if dialect.has_headers {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+    let columns = match (&dialect.columns, header_row) {
+        (Some(columns), _) => Some(StringRecord::from(columns.clone())),
+        (None, header_row) => header_row,
+    };
+    let schema = match (dialect.schema, &columns) {
+        (Some(version), _) => version,
+        (None, Some(columns)) if columns.iter().any(|h| h == "action") => 2,
+        (None, _) => 1,
+    };
+    let headers = columns.unwrap_or_else(|| match schema {
+        2 => StringRecord::from(COMMAND_COLUMNS.to_vec()),
+        _ => StringRecord::from(ORDER_COLUMNS.to_vec()),
+    });
+    let build = move |record: &StringRecord| -> Result<matcher::command::Command, String> {
+        if schema == 2 {
+            record
+                .deserialize::<CommandRecord>(Some(&headers))
+                .map_err(|e| e.to_string())
+                .and_then(CommandRecord::into_command)
+                .and_then(|c| c.build(&instrument))
+        } else {
+            record
+                .deserialize::<OrderBuilder>(Some(&headers))
+                .map_err(|e| e.to_string())
+                .and_then(|r| r.build(&instrument))
+        }
+    };
+    let commands = reader
+        .into_records()
+        .enumerate()
+        .map(|(index, result)| match result {
+            Ok(record) => Parsed {
+                number: index + 1,
+                line: record.position().map_or(0, |p| p.line()),
+                text: encode_record(&record),
+                command: build(&record),
+            },
+            Err(e) => Parsed {
+                number: index + 1,
+                line: e.position().map_or(0, |p| p.line()),
+                text: String::new(),
+                command: Err(e.to_string()),
+            },
+        });
+    process_commands(commands, "record", matcher, journal, output, rejects)
+}
+
+/// A record as it would appear in a CSV file, without the line terminator.
+fn encode_record(record: &StringRecord) -> String {
+    let mut writer = WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    let text = writer
+        .write_record(record)
+        .ok()
+        .and_then(|()| writer.into_inner().ok())
+        .unwrap_or_default();
+    String::from_utf8_lossy(&text).trim_end().to_string()
+}
+
+/// Like `process_csv`, for one JSON command per line. Blank lines are
+/// skipped, and messages give line numbers.
+fn process_jsonl<R: BufRead>(
+    reader: R,
+    matcher: &mut matcher::Matcher,
+    journal: Option<&mut Journal>,
+    output: &mut Output,
+    rejects: &mut Rejects,
+) -> Result<(), Box<dyn Error>> {
+    let instrument = *matcher.instrument();
+    let commands = reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|e| e.to_string());
+            let command = line.clone().and_then(|line| {
+                serde_json::from_str::<JsonCommand>(&line)
+                    .map_err(|e| e.to_string())
+                    .and_then(|c| c.build(&instrument))
+            });
+            Parsed {
+                number: index + 1,
+                line: index as u64 + 1,
+                text: line.unwrap_or_default(),
+                command,
+            }
+        });
+    process_commands(commands, "line", matcher, journal, output, rejects)
+}
+
+/// A command read from the input, with where it came from.
+struct Parsed {
+    /// Position of the command in the input, used in messages.
+    number: usize,
+    line: u64,
+    /// The input the command was read from.
+    text: String,
+    command: Result<matcher::command::Command, String>,
+}
+
+/// Input that could not be read as a command stops the run, unless
+/// skipping was asked for. Skipped input is counted and written to the
+/// reject file, if any.
+struct Rejects {
+    skip: bool,
+    file: Option<Writer<File>>,
+    skipped: u64,
+}
+
+impl Rejects {
+    fn new(skip: bool, path: Option<&String>) -> Result<Rejects, Box<dyn Error>> {
+        let file = match path {
+            Some(path) => {
+                let mut file = Writer::from_path(path)?;
+                file.write_record(["line", "error", "input"])?;
+                Some(file)
+            }
+            None => None,
+        };
+        Ok(Rejects {
+            skip,
+            file,
+            skipped: 0,
+        })
+    }
+
+    fn skip(&mut self, parsed: &Parsed, error: &str) -> Result<(), Box<dyn Error>> {
+        self.skipped += 1;
+        if let Some(file) = self.file.as_mut() {
+            file.write_record([parsed.line.to_string().as_str(), error, &parsed.text])?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        if self.skip {
+            eprintln!("Skipped {} malformed input records", self.skipped);
+        }
+        Ok(())
+    }
+}
+
+/// Executes commands numbered by their position in the input, called
+/// `unit` in messages. Rejected and refused commands are reported and
+/// skipped, anything else stops the run.
+fn process_commands<I>(
+    commands: I,
+    unit: &str,
+    matcher: &mut matcher::Matcher,
+    mut journal: Option<&mut Journal>,
+    output: &mut Output,
+    rejects: &mut Rejects,
+) -> Result<(), Box<dyn Error>>
+where
+    I: Iterator<Item = Parsed>,
+{
+    let mut label = unit.to_string();
+    label[..1].make_ascii_uppercase();
+    for parsed in commands {
+        let number = parsed.number;
+        match parsed.command {
+            Ok(command) => {
+                let result = match journal.as_deref_mut() {
+                    Some(journal) => matcher.execute_journaled(journal, command),
+                    None => matcher.execute(command).map_err(JournalError::from),
+                };
+                output.write(matcher)?;
+                match result {
+                    Ok(()) => {}
+                    Err(JournalError::Match(MatchError::Rejected(reason))) => {
+                        eprintln!("{} {} rejected: {}", label, number, reason);
+                    }
+                    Err(JournalError::Match(e)) if e.is_refusal() => {
+                        eprintln!("{} {} refused: {}", label, number, e);
+                    }
+                    Err(e) => {
+                        eprintln!("Error at {} {}: {}", unit, number, e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            Err(ref e) if rejects.skip => {
+                eprintln!("Skipped {} {}: {}", unit, number, e);
+                rejects.skip(&parsed, e)?;
+            }
+            Err(e) => {
+                eprintln!("Error at {} {}: {}", unit, number, e);
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Settings of a new matcher, shared by the subcommands that create one.
+fn engine_args() -> Vec<Arg> {
+    let mut args = scale_args().to_vec();
+    args.extend([
+        Arg::new("rounding")
+            .long("rounding")
+            .help("How to handle values with more decimal places than the scale")
+            .value_parser(["reject", "down", "half-up"])
+            .default_value("reject"),
+        Arg::new("allocation")
+            .long("allocation")
+            .help("How fills are shared between the orders of a price level")
+            .value_parser(["fifo", "pro-rata", "fifo-top-pro-rata"])
+            .default_value("fifo"),
+        Arg::new("min-allocation")
+            .long("min-allocation")
+            .help("Smallest pro-rata share, in quantity units")
+            .value_parser(value_parser!(u64))
+            .default_value("0"),
+        Arg::new("deterministic")
+            .long("deterministic")
+            .help("Give orders sequential ids instead of random ones")
+            .action(ArgAction::SetTrue),
+        Arg::new("seed")
+            .long("seed")
+            .help("Give orders pseudo-random ids generated from this seed")
+            .value_parser(value_parser!(u64))
+            .conflicts_with("deterministic"),
+    ]);
+    args
+}
+
+/// Decimal places of prices and quantities.
+fn scale_args() -> [Arg; 2] {
+    [
+        Arg::new("price-scale")
+            .long("price-scale")
+            .help("Number of decimal places in prices")
+            .value_parser(value_parser!(u32).range(0..=i64::from(MAX_SCALE)))
+            .default_value("0"),
+        Arg::new("qty-scale")
+            .long("qty-scale")
+            .help("Number of decimal places in quantities")
+            .value_parser(value_parser!(u32).range(0..=i64::from(MAX_SCALE)))
+            .default_value("0"),
+    ]
+}
+
+/// The instrument given by `engine_args`.
+fn instrument_from(matches: &ArgMatches) -> Result<Instrument, Box<dyn Error>> {
+    let rounding: Rounding = matches
+        .get_one::<String>("rounding")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let min_qty = *matches.get_one::<u64>("min-allocation").unwrap();
+    let allocation = match matches.get_one::<String>("allocation").unwrap().as_str() {
+        "pro-rata" => Allocation::ProRata { min_qty },
+        "fifo-top-pro-rata" => Allocation::FifoTopProRata { min_qty },
+        _ => Allocation::Fifo,
+    };
+    Ok(Instrument::new(
+        *matches.get_one::<u32>("price-scale").unwrap(),
+        *matches.get_one::<u32>("qty-scale").unwrap(),
+    )?
+    .with_rounding(rounding)
+    .with_allocation(allocation))
+}
+
+/// A matcher with the settings given by `engine_args`.
+fn matcher_from(matches: &ArgMatches) -> Result<matcher::Matcher, Box<dyn Error>> {
+    let ids = match matches.get_one::<u64>("seed") {
+        Some(seed) => IdGenerator::seeded(*seed),
+        None if matches.get_flag("deterministic") => IdGenerator::sequential(),
+        None => IdGenerator::Random,
+    };
+    Ok(matcher::Matcher::with_instrument(instrument_from(matches)?).with_ids(ids))
+}
+
+/// Asks from the worst price down to the best one, then bids from the best
+/// one down, so that the spread is in the middle.
+fn print_book(matcher: &matcher::Matcher) {
+    let instrument = matcher.instrument();
+    let asks: Vec<_> = matcher.book().depth(Side::Sell).collect();
+    for (price, qty, count) in asks.into_iter().rev() {
+        println!(
+            "Sell {} x {} ({} orders)",
+            instrument.format_price(price),
+            instrument.format_qty(qty),
+            count
+        );
+    }
+    println!("--");
+    for (price, qty, count) in matcher.book().depth(Side::Buy) {
+        println!(
+            "Buy {} x {} ({} orders)",
+            instrument.format_price(price),
+            instrument.format_qty(qty),
+            count
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct OrderReport {
+    order_id: String,
+    user_id: u64,
+    order_type: matcher::order::OrderType,
+    side: Side,
+    price: String,
+    remaining_qty: String,
+    initial_qty: String,
+    session_id: Option<u64>,
+}
+
+impl OrderReport {
+    fn new(o: &Order, instrument: &Instrument) -> OrderReport {
+        OrderReport {
+            order_id: o.id().to_string(),
+            user_id: o.user_id(),
+            order_type: o.order_type(),
+            side: o.side(),
+            price: instrument.format_price(o.price()),
+            remaining_qty: instrument.format_qty(o.current_qty()),
+            initial_qty: instrument.format_qty(o.initial_qty()),
+            session_id: o.session_id(),
+        }
+    }
+}
+
+/// Prints the book of a snapshot: its price levels, or with `--orders`
+/// every resting order in priority order.
+fn print_snapshot(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.get_one::<String>("snapshot").unwrap();
+    let matcher = matcher::Matcher::load_snapshot(BufReader::new(File::open(path)?))?;
+    if !matches.get_flag("orders") {
+        print_book(&matcher);
+        return Ok(());
+    }
+    let instrument = matcher.instrument();
+    let mut writer = Writer::from_writer(io::stdout());
+    for side in [Side::Buy, Side::Sell] {
+        for o in matcher.book().orders(side) {
+            writer.serialize(OrderReport::new(o, instrument))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
 
-    for (index, result) in reader.deserialize::<OrderBuilder>().enumerate() {
+/// Applies the commands of a journal to a new matcher, or to one restored
+/// from a snapshot, and prints the events they produce.
+fn replay(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let records = Journal::read(matches.get_one::<String>("journal").unwrap())?;
+    let mut matcher = match matches.get_one::<String>("snapshot-in") {
+        Some(path) => matcher::Matcher::load_snapshot(BufReader::new(File::open(path)?))?,
+        None => matcher_from(matches)?,
+    };
+    let format: Format = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut output = Output {
+        events: EventWriter::new(BufWriter::new(io::stdout()), format, *matcher.instrument()),
+        trades: None,
+        flush_each: false,
+    };
+    for record in records {
+        let seq = record.seq;
+        let result = matcher.apply_record(record);
+        output.write(&mut matcher)?;
         match result {
-            Ok(record) => {
-                matcher.proceed_record(matcher::order::Order::new(
-                    record.order_type,
-                    record.side,
-                    record.price,
-                    record.initial_qty,
-                    record.user_id,
-                ));
+            Ok(()) => {}
+            Err(MatchError::Rejected(reason)) => {
+                eprintln!("Record {} rejected: {}", seq, reason);
             }
+            Err(e) if e.is_refusal() => eprintln!("Record {} refused: {}", seq, e),
             Err(e) => {
-                eprintln!("Error at record {}: {}", index + 1, e);
+                eprintln!("Error at record {}: {}", seq, e);
                 return Err(e.into());
             }
         }
     }
+    output.flush()?;
+
+    if matches.get_flag("digest") {
+        eprintln!("Digest: {:016x}", matcher.digest());
+    }
+    if let Some(path) = matches.get_one::<String>("snapshot-out") {
+        matcher.save_snapshot(BufWriter::new(File::create(path)?))?;
+    }
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let matches = Command::new("Matcher")
+/// Names of the subcommands. Arguments that do not start with one are
+/// taken as those of `run`, so `matcher orders.csv` keeps working.
+const SUBCOMMANDS: [&str; 10] = [
+    "run", "replay", "snapshot", "stats", "gen", "repl", "serve", "fix", "http", "help",
+];
+
+fn cli() -> Command {
+    Command::new("Matcher")
         .version("0.1.0")
         .author("Author")
         .about("A trading order matching engine")
+        .subcommand_required(true)
+        .subcommand(run_command())
+        .subcommand(
+            Command::new("replay")
+                .about("Replay the commands of a journal and print their events")
+                .arg(
+                    Arg::new("journal")
+                        .help("Journal written by `run --journal`")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("snapshot-in")
+                        .long("snapshot-in")
+                        .help("Start from a snapshot and replay only the commands after it"),
+                )
+                .arg(
+                    Arg::new("snapshot-out")
+                        .long("snapshot-out")
+                        .help("Save the state after the replay to a snapshot"),
+                )
+                .arg(format_arg())
+                .arg(digest_arg())
+                .args(engine_args()),
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Print the book saved in a snapshot")
+                .arg(Arg::new("snapshot").help("Snapshot file").required(true))
+                .arg(
+                    Arg::new("orders")
+                        .long("orders")
+                        .help("List every resting order as CSV instead of the price levels")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Summarise an output log in any of the output formats")
+                .arg(
+                    Arg::new("log")
+                        .help("Output of `run`, or - to read from stdin")
+                        .default_value("-"),
+                )
+                .args(scale_args()),
+        )
+        .subcommand(gen::command())
+        .subcommand(
+            Command::new("repl")
+                .about("Type orders and queries against a live matcher")
+                .args(engine_args()),
+        )
+        .subcommand(gateway::command())
+        .subcommand(fix_gateway::command())
+        .subcommand(http::command())
+}
+
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .help("Output format: text lines, one JSON object or one CSV row per event and trade")
+        .value_parser(["text", "jsonl", "csv"])
+        .default_value("text")
+}
+
+fn digest_arg() -> Arg {
+    Arg::new("digest")
+        .long("digest")
+        .help("Print a digest of the final book state to stderr")
+        .action(ArgAction::SetTrue)
+}
+
+fn run_command() -> Command {
+    Command::new("run")
+        .about("Process orders from a file or stdin; the default")
         .arg(
             Arg::new("input")
-                .help("Input CSV file with order data")
-                .required(true)
+                .help("Input CSV file with order data, or - to read from stdin")
+                .default_value("-")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("input-format")
+                .long("input-format")
+                .help("Input format: CSV records, or one JSON command per line")
+                .value_parser(["csv", "jsonl"])
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("csv-schema")
+                .long("csv-schema")
+                .help("Version of the CSV records: 1 for orders, 2 for commands (default: from the header)")
+                .value_parser(clap::value_parser!(u32).range(1..=2)),
+        )
+        .arg(
+            Arg::new("delimiter")
+                .long("delimiter")
+                .help("Field delimiter of CSV input: a single character, or `tab`")
+                .value_parser(parse_delimiter)
+                .default_value(","),
+        )
+        .arg(
+            Arg::new("no-header")
+                .long("no-header")
+                .help("CSV input has no header row; columns are taken from --columns or the default order of the schema")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .help("Comma-separated names of the CSV input columns in file order, replacing the header row; `_` skips a column"),
+        )
+        .arg(
+            Arg::new("on-error")
+                .long("on-error")
+                .help("What to do with malformed input: stop the run, or skip it and go on")
+                .value_parser(["abort", "skip"])
+                .default_value("abort"),
+        )
+        .arg(
+            Arg::new("reject-file")
+                .long("reject-file")
+                .help("File receiving input skipped by --on-error skip, with its line number and error"),
+        )
+        .arg(
+            Arg::new("snapshot-in")
+                .long("snapshot-in")
+                .help("Resume from a snapshot; its instrument settings replace the ones given here")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("snapshot-out")
+                .long("snapshot-out")
+                .help("Save the book to a snapshot at the end instead of canceling resting orders")
                 .action(ArgAction::Set),
         )
-        .get_matches();
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .help("Append every command to this journal before applying it; commands already in it are replayed first")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("balances")
+                .long("balances")
+                .help("Check orders against account balances, starting from this CSV file of user_id,base,quote")
+                .conflicts_with("snapshot-in")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("balances-out")
+                .long("balances-out")
+                .help("Write the account balances at the end of the run to this CSV file")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("positions-out")
+                .long("positions-out")
+                .help("Write the position and PnL of every user at the end of the run to this CSV file")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("mark")
+                .long("mark")
+                .help("Price to value open positions at: the last trade or the mid of the book")
+                .value_parser(["last", "mid"])
+                .default_value("last"),
+        )
+        .arg(
+            Arg::new("maker-fee")
+                .long("maker-fee")
+                .help("Fee of resting orders, as <n>bps or quote per quantity unit; negative for a rebate")
+                .allow_hyphen_values(true)
+                .default_value("0bps"),
+        )
+        .arg(
+            Arg::new("taker-fee")
+                .long("taker-fee")
+                .help("Fee of incoming orders, as <n>bps or quote per quantity unit; negative for a rebate")
+                .allow_hyphen_values(true)
+                .default_value("0bps"),
+        )
+        .arg(
+            Arg::new("fee-tiers")
+                .long("fee-tiers")
+                .help("CSV file of user_id,maker,taker fee rates replacing the default ones for those users")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("trades-out")
+                .long("trades-out")
+                .help("Write every trade with the fees of both sides to this CSV file")
+                .action(ArgAction::Set),
+        )
+        .arg(format_arg())
+        .arg(digest_arg())
+        .args(engine_args())
+}
 
+/// The arguments of the process, with `run` put in front unless they start
+/// with a subcommand or ask for help or the version.
+fn args() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let named = args.get(1).and_then(|a| a.to_str()).is_some_and(|a| {
+        SUBCOMMANDS.contains(&a) || matches!(a, "-h" | "--help" | "-V" | "--version")
+    });
+    if !named {
+        args.insert(1.min(args.len()), "run".into());
+    }
+    args
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = cli().get_matches_from(args());
+    match matches.subcommand() {
+        Some(("run", matches)) => run(matches),
+        Some(("replay", matches)) => replay(matches),
+        Some(("snapshot", matches)) => print_snapshot(matches),
+        Some(("stats", matches)) => stats::run(matches),
+        Some(("gen", matches)) => gen::run(matches),
+        Some(("repl", matches)) => repl::run(matcher_from(matches)?),
+        Some(("serve", matches)) => gateway::run(matches),
+        Some(("fix", matches)) => fix_gateway::run(matches),
+        Some(("http", matches)) => http::run(matches),
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let input_path = PathBuf::from(matches.get_one::<String>("input").unwrap());
-    if !input_path.exists() {
+    let from_stdin = input_path.as_os_str() == "-";
+    if !from_stdin && !input_path.exists() {
         eprintln!(
             "Error: Input file '{}' does not exist",
             input_path.display()
@@ -62,11 +963,123 @@ fn main() -> Result<(), Box<dyn Error>> {
         return Err("File not found".into());
     }
 
-    match process_csv(&input_path) {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            eprintln!("Error processing file: {}", e);
-            Err(e)
+    let matcher = match matches.get_one::<String>("snapshot-in") {
+        Some(path) => matcher::Matcher::load_snapshot(BufReader::new(File::open(path)?))?,
+        None => {
+            let matcher = matcher_from(matches)?;
+            let instrument = *matcher.instrument();
+            match matches.get_one::<String>("balances") {
+                Some(path) => matcher.with_accounts(read_balances(path, &instrument)?),
+                None => matcher,
+            }
         }
+    };
+
+    // Per-unit fees are amounts of quote, so they take the scales of the
+    // instrument in use, which may come from the snapshot
+    let instrument = *matcher.instrument();
+    let mut fees = FeeSchedule::new(FeeTier {
+        maker: parse_fee_rate(matches.get_one::<String>("maker-fee").unwrap(), &instrument)?,
+        taker: parse_fee_rate(matches.get_one::<String>("taker-fee").unwrap(), &instrument)?,
+    });
+    if let Some(path) = matches.get_one::<String>("fee-tiers") {
+        fees = read_fee_tiers(path, fees, &instrument)?;
     }
+    let mut matcher = matcher.with_fees(fees);
+
+    let format: Format = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let mut output = Output {
+        events: EventWriter::new(BufWriter::new(io::stdout()), format, instrument),
+        trades: match matches.get_one::<String>("trades-out") {
+            Some(path) => Some(Writer::from_path(path)?),
+            None => None,
+        },
+        flush_each: from_stdin,
+    };
+
+    let mut journal = match matches.get_one::<String>("journal") {
+        Some(path) => {
+            let (mut journal, records) = Journal::open(path)?;
+            journal.replay(&mut matcher, records)?;
+            Some(journal)
+        }
+        None => None,
+    };
+
+    let input: Box<dyn BufRead> = if from_stdin {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&input_path)?))
+    };
+    let mut rejects = Rejects::new(
+        matches.get_one::<String>("on-error").unwrap() == "skip",
+        matches.get_one::<String>("reject-file"),
+    )?;
+    let processed = match matches.get_one::<String>("input-format").unwrap().as_str() {
+        "jsonl" => process_jsonl(
+            input,
+            &mut matcher,
+            journal.as_mut(),
+            &mut output,
+            &mut rejects,
+        ),
+        _ => {
+            let dialect = CsvDialect {
+                schema: matches.get_one::<u32>("csv-schema").copied(),
+                delimiter: *matches.get_one::<u8>("delimiter").unwrap(),
+                has_headers: !matches.get_flag("no-header"),
+                columns: matches
+                    .get_one::<String>("columns")
+                    .map(|c| c.split(',').map(|name| name.trim().to_string()).collect()),
+            };
+            process_csv(
+                input,
+                &dialect,
+                &mut matcher,
+                journal.as_mut(),
+                &mut output,
+                &mut rejects,
+            )
+        }
+    };
+    rejects.finish()?;
+    if let Err(e) = processed {
+        eprintln!("Error processing file: {}", e);
+        return Err(e);
+    }
+
+    if matches.get_flag("digest") {
+        eprintln!("Digest: {:016x}", matcher.digest());
+    }
+
+    // Valued before shutdown empties the book the mid price is taken from
+    if let Some(path) = matches.get_one::<String>("positions-out") {
+        let mark: Mark = matches.get_one::<String>("mark").unwrap().parse().unwrap();
+        write_positions(path, &matcher, mark)?;
+    }
+
+    match matches.get_one::<String>("snapshot-out") {
+        Some(path) => matcher.save_snapshot(BufWriter::new(File::create(path)?))?,
+        None => {
+            // Journaled, so that a restart does not bring back the orders
+            // reported as leaving here
+            match journal.as_mut() {
+                Some(journal) => {
+                    matcher.execute_journaled(journal, matcher::command::Command::Shutdown)?
+                }
+                None => matcher.shutdown(),
+            }
+            output.write(&mut matcher)?;
+        }
+    }
+    output.flush()?;
+
+    if let Some(path) = matches.get_one::<String>("balances-out") {
+        write_balances(path, &matcher)?;
+    }
+    Ok(())
 }
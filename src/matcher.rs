@@ -1,20 +1,53 @@
-use std::collections::VecDeque;
-
+pub mod accounts;
+pub mod allocation;
+pub mod amend;
 pub mod book;
+pub mod cancel;
+pub mod command;
+pub mod error;
+pub mod event;
+pub mod fee;
+pub mod fix;
+pub mod input;
+pub mod instrument;
+pub mod journal;
+pub mod notional;
 pub mod order;
+pub mod output;
+pub mod policy;
+pub mod position;
+pub mod session;
+pub mod snapshot;
+pub mod trade;
+
+use std::collections::BTreeMap;
+
+use error::MatchError;
+use event::{Event, EventKind};
+use notional::Notional;
+use policy::{Eligibility, MatchingPolicy, StandardPolicy};
 
-#[derive(Default)]
 pub struct Matcher {
     g: book::Book,
-    orders_to_recover: VecDeque<order::Order>,
+    events: Vec<Event>,
+    instrument: instrument::Instrument,
+    traded_notional: Notional,
+    policy: Box<dyn MatchingPolicy>,
+    sequence: u64,
+    ids: order::IdGenerator,
+    sessions: BTreeMap<u64, session::Session>,
+    accounts: Option<accounts::Accounts>,
+    positions: position::Positions,
+    fees: fee::FeeSchedule,
+    trades: Vec<trade::Trade>,
+    /// Sequence number of the last event or trade produced.
+    event_seq: u64,
 }
 
-#[derive(Copy, Clone)]
-enum MatchResult {
-    Ok,
-    Discrepancy,
-    SameUser,
-    SameSide,
+impl Default for Matcher {
+    fn default() -> Matcher {
+        Matcher::with_instrument(instrument::Instrument::default())
+    }
 }
 
 impl Matcher {
@@ -23,150 +56,329 @@ impl Matcher {
             ..Default::default()
         }
     }
-}
 
-fn choose_prices(lhs: &order::Order, rhs: &order::Order) -> (u64, u64) {
-    if lhs.side() == order::Side::Buy {
-        return (lhs.price(), rhs.price());
-    } else {
-        return (rhs.price(), lhs.price());
+    /// Creates a matcher for an instrument with the given price and quantity
+    /// scales. Matching itself only ever sees the scaled integers.
+    pub fn with_instrument(instrument: instrument::Instrument) -> Matcher {
+        Matcher {
+            g: book::Book::default(),
+            events: Vec::new(),
+            instrument,
+            traded_notional: Notional::ZERO,
+            policy: Box::new(StandardPolicy::new(instrument.allocation())),
+            sequence: 0,
+            ids: order::IdGenerator::default(),
+            sessions: BTreeMap::new(),
+            accounts: None,
+            positions: position::Positions::default(),
+            fees: fee::FeeSchedule::default(),
+            trades: Vec::new(),
+            event_seq: 0,
+        }
+    }
+
+    /// Replaces the rules used to match orders. By default a matcher uses
+    /// `StandardPolicy` with the allocation of its instrument.
+    pub fn with_policy(mut self, policy: Box<dyn MatchingPolicy>) -> Matcher {
+        self.policy = policy;
+        self
+    }
+
+    /// Makes the matcher give its own ids to incoming orders, replacing the
+    /// random ones of `Order::new`. Together with the strict price-time
+    /// priority of the book this makes two runs over the same input end in
+    /// the same state, down to the order ids.
+    pub fn with_ids(mut self, ids: order::IdGenerator) -> Matcher {
+        self.ids = ids;
+        self
+    }
+
+    /// Charges fees on every trade as given by `fees`. By default trading
+    /// is free. Set before any order locks funds, as buy orders lock their
+    /// fees too.
+    pub fn with_fees(mut self, fees: fee::FeeSchedule) -> Matcher {
+        self.fees = fees;
+        self
+    }
+
+    pub fn instrument(&self) -> &instrument::Instrument {
+        &self.instrument
+    }
+
+    /// The resting orders.
+    pub fn book(&self) -> &book::Book {
+        &self.g
+    }
+
+    /// Total value of all trades so far, each priced as decided by the policy.
+    pub fn traded_notional(&self) -> Notional {
+        self.traded_notional
+    }
+
+    /// Sequence number of the last journaled command applied, 0 if none.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Takes the events produced since the previous call, oldest first.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, Event> {
+        self.events.drain(..)
+    }
+
+    /// Queues an event, numbering it after everything produced so far.
+    pub(crate) fn emit(&mut self, mut event: Event) {
+        self.event_seq += 1;
+        event.seq = self.event_seq;
+        self.events.push(event);
+    }
+
+    /// Takes the trades made since the previous call, oldest first.
+    pub fn drain_trades(&mut self) -> std::vec::Drain<'_, trade::Trade> {
+        self.trades.drain(..)
+    }
+
+    /// Removes every resting order from the book, reporting each one as it
+    /// leaves the engine.
+    pub fn shutdown(&mut self) {
+        for side in [order::Side::Buy, order::Side::Sell] {
+            while let Some(o) = self.g.pop(side) {
+                self.unlock_funds(&o);
+                self.emit(Event::terminal(&o));
+            }
+        }
     }
 }
 
 fn opposite_side(o: &order::Order) -> order::Side {
     if o.side().eq(&order::Side::Buy) {
-        return order::Side::Sell;
+        order::Side::Sell
     } else {
-        return order::Side::Buy;
+        order::Side::Buy
     }
 }
 
-fn orders_match(lhs: &order::Order, rhs: &order::Order) -> MatchResult {
-    let lhs_side = lhs.side();
-    let rhs_side = rhs.side();
-    if lhs_side == rhs_side {
-        return MatchResult::SameSide;
-    }
-    if lhs.user_id() == rhs.user_id() {
-        return MatchResult::SameUser;
-    }
-    let (a, b) = choose_prices(lhs, rhs);
-    if a >= b {
-        return MatchResult::Ok;
-    }
-    return MatchResult::Discrepancy;
+/// What a sweep of the book by an incoming order changes, worked out on
+/// copies of the state it touches so that nothing changes unless every
+/// fill of the sweep succeeds.
+struct Sweep {
+    /// The levels visited, best first, as the sweep leaves them.
+    levels: Vec<(u64, book::Level)>,
+    /// Trades and resting orders filled, in the order they happened.
+    outputs: Vec<SweepOutput>,
+    traded: Notional,
+    /// The accounts changed, if the matcher keeps balances.
+    accounts: Option<accounts::Accounts>,
 }
 
-impl Matcher {
-    fn put_recovered_orders_back(&mut self) {
-        let mut reconfig_needed = false;
-        let mut border_price: u64 = 0;
-        let mut side = order::Side::Buy;
-        if let Some(o) = self.orders_to_recover.back() {
-            side = o.side();
-            border_price = o.price();
-            if let Some(front) = self.g.peek_mut(o.side()) {
-                if front.price() == border_price {
-                    reconfig_needed = true;
-                }
-            }
+enum SweepOutput {
+    Trade(trade::Trade),
+    Filled(order::Order),
+}
+
+/// Positions of the orders of a level that may trade with `o`, in time
+/// priority, and whether some order of the level has to stay untouched, so
+/// matching must not go on to worse levels.
+fn eligible_orders(
+    o: &order::Order,
+    level: &book::Level,
+    policy: &dyn MatchingPolicy,
+) -> Result<(Vec<usize>, bool), MatchError> {
+    let mut eligible = Vec::new();
+    let mut blocked = false;
+    for (i, resting) in level.iter().enumerate() {
+        if resting.side() == o.side() {
+            return Err(MatchError::SameSide);
         }
-        if reconfig_needed {
-            loop {
-                if let Some(front) = self.g.peek_mut(side) {
-                    if front.price() != border_price {
-                        break;
-                    }
-                } else {
-                    break;
-                }
-                if let Some(front) = self.g.pop(side) {
-                    self.orders_to_recover.push_back(front);
-                }
+        match policy.eligibility(o, resting) {
+            Eligibility::Trade => eligible.push(i),
+            Eligibility::Skip => blocked = true,
+            Eligibility::Stop => {
+                blocked = true;
+                break;
             }
         }
-        while let Some(o) = self.orders_to_recover.pop_front() {
-            self.g.push(o);
-        }
     }
-    fn process_lim(&mut self, mut o: order::Order) {
-        o = self.common_processing(o);
+    Ok((eligible, blocked))
+}
+
+impl Matcher {
+    fn process_lim(&mut self, mut o: order::Order) -> Result<(), MatchError> {
+        o = self.common_processing(o)?;
         if o.current_qty() != 0 {
-            o.print_due_external_event(order::ExternalEvent::Queued);
+            self.emit(Event::new(EventKind::Queued, &o));
             self.g.push(o);
+        } else {
+            self.emit(Event::terminal(&o));
         }
+        Ok(())
     }
-    fn process_ioc(&mut self, o: order::Order) {
-        self.common_processing(o);
+    fn process_ioc(&mut self, o: order::Order) -> Result<(), MatchError> {
+        let o = self.common_processing(o)?;
+        self.unlock_funds(&o);
+        self.emit(Event::terminal(&o));
+        Ok(())
     }
-    fn process_fok(&mut self, mut o: order::Order) {
-        if !self.orders_to_recover.is_empty() {
-            panic!("Orders to recover queue is not empty in the start of fok-processing");
+    fn process_fok(&mut self, mut o: order::Order) -> Result<(), MatchError> {
+        if self.fillable_qty(&o)? >= o.current_qty() {
+            o = self.common_processing(o)?;
         }
-        while let Some(mut opposite_order) = self.g.pop(opposite_side(&o)) {
-            match orders_match(&o, &opposite_order) {
-                MatchResult::Ok => {
-                    let order_current_qty = o.current_qty();
-                    let opposite_order_current_qty = opposite_order.current_qty();
-                    if order_current_qty > opposite_order_current_qty {
-                        o.reduce_quantity(opposite_order_current_qty);
-                        self.orders_to_recover.push_back(opposite_order);
-                    } else {
-                        opposite_order.reduce_quantity(order_current_qty);
-                        o.reduce_quantity(order_current_qty);
-                        if order_current_qty < opposite_order_current_qty {
-                            self.orders_to_recover.push_back(opposite_order);
-                        }
-                        break;
+        self.unlock_funds(&o);
+        self.emit(Event::terminal(&o));
+        Ok(())
+    }
+    pub fn proceed_record(&mut self, mut o: order::Order) -> Result<(), MatchError> {
+        if let Some(id) = self.ids.next_id() {
+            o.set_id(id);
+        }
+        if let Err(reason) = o
+            .validate()
+            .and_then(|()| self.check_session(&o))
+            .and_then(|()| self.lock_funds(&o))
+        {
+            self.emit(Event::rejected(&o, reason));
+            return Err(MatchError::Rejected(reason));
+        }
+        self.emit(Event::new(EventKind::Accepted, &o));
+        match o.order_type() {
+            order::OrderType::Lim => self.process_lim(o),
+            order::OrderType::Ioc => self.process_ioc(o),
+            order::OrderType::Fok => self.process_fok(o),
+        }
+    }
+    /// How much of `o` could be executed right now, without touching the book.
+    fn fillable_qty(&self, o: &order::Order) -> Result<u64, MatchError> {
+        let mut available: u64 = 0;
+        for (_, level) in self.g.levels(opposite_side(o)) {
+            let (eligible, blocked) = eligible_orders(o, level, self.policy.as_ref())?;
+            for i in eligible {
+                available = available.saturating_add(level[i].current_qty());
+            }
+            if blocked || available >= o.current_qty() {
+                break;
+            }
+        }
+        Ok(available)
+    }
+    /// Matches `o` against the book. An error leaves the book, the balances
+    /// and the traded notional as they were, and `o` leaves the engine.
+    fn common_processing(&mut self, o: order::Order) -> Result<order::Order, MatchError> {
+        let mut taker = o.clone();
+        match self.sweep(&mut taker) {
+            Ok(sweep) => {
+                self.apply(opposite_side(&o), sweep);
+                Ok(taker)
+            }
+            Err(e) => {
+                self.unlock_funds(&o);
+                self.emit(Event::terminal(&o));
+                Err(e)
+            }
+        }
+    }
+    fn sweep(&self, o: &mut order::Order) -> Result<Sweep, MatchError> {
+        let mut sweep = Sweep {
+            levels: Vec::new(),
+            outputs: Vec::new(),
+            traded: self.traded_notional,
+            accounts: self.accounts.as_ref().map(|_| accounts::Accounts::new()),
+        };
+        for (price, level) in self.g.levels(opposite_side(o)) {
+            if o.current_qty() == 0 {
+                break;
+            }
+            let mut level = level.clone();
+            let mut done = false;
+            while o.current_qty() != 0 && !level.is_empty() {
+                let (eligible, blocked) = eligible_orders(o, &level, self.policy.as_ref())?;
+                let resting: Vec<&order::Order> = eligible.iter().map(|&i| &level[i]).collect();
+                let fills = self.policy.allocate(o.current_qty(), &resting);
+                let qty_before = o.current_qty();
+                for (&i, qty) in eligible.iter().zip(fills) {
+                    if qty != 0 {
+                        let fill = self.trade(o, &mut level[i], qty, &mut sweep)?;
+                        sweep.outputs.push(SweepOutput::Trade(fill));
                     }
                 }
-                MatchResult::SameSide => panic!("Orders of the same side"),
-                MatchResult::SameUser | MatchResult::Discrepancy => {
-                    self.orders_to_recover.push_back(opposite_order);
+                let (filled, resting): (book::Level, book::Level) =
+                    level.drain(..).partition(|o| o.current_qty() == 0);
+                level = resting;
+                sweep
+                    .outputs
+                    .extend(filled.into_iter().map(SweepOutput::Filled));
+                // A policy allocating nothing would otherwise never leave the level
+                if blocked || o.current_qty() == qty_before {
+                    done = true;
                     break;
                 }
             }
+            sweep.levels.push((price, level));
+            if done {
+                break;
+            }
         }
-        self.put_recovered_orders_back();
+        Ok(sweep)
     }
-    pub fn proceed_record(&mut self, o: order::Order) {
-        o.print_due_external_event(order::ExternalEvent::Accepted);
-        match o.order_type() {
-            order::OrderType::Lim => self.process_lim(o),
-            order::OrderType::Ioc => self.process_ioc(o),
-            order::OrderType::Fok => self.process_fok(o),
+    /// Executes `qty` of `o` against `resting` at the price chosen by the
+    /// policy, adding the value of the trade to the sweep and settling it
+    /// between the two accounts.
+    fn trade(
+        &self,
+        o: &mut order::Order,
+        resting: &mut order::Order,
+        qty: u64,
+        sweep: &mut Sweep,
+    ) -> Result<trade::Trade, MatchError> {
+        let price = self.policy.execution_price(o, resting);
+        let (buy, sell) = if o.side() == order::Side::Buy {
+            (&*o, &*resting)
+        } else {
+            (&*resting, &*o)
+        };
+        if price > buy.price() || price < sell.price() {
+            return Err(MatchError::ExecutionPrice(price));
         }
+        sweep.traded = sweep
+            .traded
+            .checked_add(Notional::new(price, qty))
+            .ok_or(MatchError::NotionalOverflow)?;
+        // The incoming order takes the liquidity of the resting one
+        let mut fill = trade::Trade::new(o, resting, price, qty);
+        fill.taker_fee = self.fees.fee(fill.taker_user_id, false, price, qty);
+        fill.maker_fee = self.fees.fee(fill.maker_user_id, true, price, qty);
+        if let (Some(changed), Some(accounts)) = (sweep.accounts.as_mut(), self.accounts.as_ref()) {
+            accounts::settle(changed, accounts, &self.fees, buy, sell, &fill)?;
+        }
+        resting.reduce_quantity(qty)?;
+        o.reduce_quantity(qty)?;
+        Ok(fill)
     }
-    fn common_processing(&mut self, mut o: order::Order) -> order::Order {
-        let o_side = opposite_side(&o);
-        while let Some(opposite_order) = self.g.peek_mut(o_side) {
-            match orders_match(&o, &opposite_order) {
-                MatchResult::Ok => {
-                    let order_current_qty = o.current_qty();
-                    let opposite_order_current_qty = opposite_order.current_qty();
-                    if order_current_qty > opposite_order_current_qty {
-                        o.reduce_quantity(opposite_order_current_qty);
-                        self.g.pop(o_side);
-                    } else {
-                        opposite_order.reduce_quantity(order_current_qty);
-                        o.reduce_quantity(order_current_qty);
-                        if opposite_order_current_qty == order_current_qty {
-                            self.g.pop(o_side);
-                        }
-                        break;
-                    }
+    /// Keeps the changes of a sweep of the `side` of the book.
+    fn apply(&mut self, side: order::Side, sweep: Sweep) {
+        for (price, level) in sweep.levels {
+            self.g.set_level(side, price, level);
+        }
+        self.traded_notional = sweep.traded;
+        if let (Some(accounts), Some(changed)) = (self.accounts.as_mut(), sweep.accounts) {
+            accounts.update(changed);
+        }
+        for output in sweep.outputs {
+            match output {
+                SweepOutput::Trade(mut fill) => {
+                    self.positions.record(&fill);
+                    self.event_seq += 1;
+                    fill.seq = self.event_seq;
+                    self.trades.push(fill);
                 }
-                MatchResult::SameSide => panic!("Orders of the same side"),
-                MatchResult::SameUser | MatchResult::Discrepancy => break,
+                SweepOutput::Filled(filled) => self.emit(Event::terminal(&filled)),
             }
         }
-        return o;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use allocation::Allocation;
 
     #[test]
     fn test_limit_order_matching() {
@@ -192,8 +404,8 @@ mod tests {
         );
 
         // Process the orders
-        matcher.proceed_record(buy_order);
-        matcher.proceed_record(sell_order);
+        matcher.proceed_record(buy_order).unwrap();
+        matcher.proceed_record(sell_order).unwrap();
 
         // The buy order should be partially filled and remain in the book
         // The sell order should be fully executed
@@ -207,7 +419,7 @@ mod tests {
             3,  // user_id
         );
 
-        matcher.proceed_record(sell_order2);
+        matcher.proceed_record(sell_order2).unwrap();
 
         // Now the buy order should be fully executed and removed from the book
     }
@@ -225,7 +437,7 @@ mod tests {
             10,  // quantity
             1,   // user_id
         );
-        matcher.proceed_record(buy_limit);
+        matcher.proceed_record(buy_limit).unwrap();
 
         // Try to match with a FOK sell order that's too large to fill completely
         let large_fok_sell = order::Order::new(
@@ -237,7 +449,7 @@ mod tests {
         );
 
         // This should be canceled
-        matcher.proceed_record(large_fok_sell);
+        matcher.proceed_record(large_fok_sell).unwrap();
 
         // Try a FOK sell that can be filled
         let matching_fok_sell = order::Order::new(
@@ -249,7 +461,7 @@ mod tests {
         );
 
         // This should execute
-        matcher.proceed_record(matching_fok_sell);
+        matcher.proceed_record(matching_fok_sell).unwrap();
 
         // The buy limit order should now be fully executed
     }
@@ -267,7 +479,7 @@ mod tests {
             10,  // quantity
             1,   // user_id
         );
-        matcher.proceed_record(buy_limit);
+        matcher.proceed_record(buy_limit).unwrap();
 
         // Match with an IOC sell order
         let ioc_sell = order::Order::new(
@@ -279,8 +491,270 @@ mod tests {
         );
 
         // Should partially execute (10 units) and cancel the rest (5 units)
-        matcher.proceed_record(ioc_sell);
+        matcher.proceed_record(ioc_sell).unwrap();
 
         // The buy limit order should now be fully executed
     }
+
+    fn event_kinds(matcher: &mut Matcher) -> Vec<EventKind> {
+        matcher.drain_events().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn test_invalid_order_is_rejected() {
+        let mut matcher = Matcher::new();
+
+        let zero_qty = order::Order::new(order::OrderType::Lim, order::Side::Buy, 100, 0, 1);
+        assert_eq!(
+            matcher.proceed_record(zero_qty),
+            Err(MatchError::Rejected(error::RejectReason::ZeroQuantity))
+        );
+        assert_eq!(event_kinds(&mut matcher), vec![EventKind::Rejected]);
+
+        // The engine keeps working after a rejection
+        let valid = order::Order::new(order::OrderType::Lim, order::Side::Buy, 100, 5, 1);
+        matcher.proceed_record(valid).unwrap();
+        assert_eq!(
+            event_kinds(&mut matcher),
+            vec![EventKind::Accepted, EventKind::Queued]
+        );
+    }
+
+    #[test]
+    fn test_fok_over_several_orders() {
+        let mut matcher = Matcher::new();
+
+        let sell1 = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 5, 1);
+        let sell2 = order::Order::new(order::OrderType::Lim, order::Side::Sell, 101, 5, 2);
+        matcher.proceed_record(sell1).unwrap();
+        matcher.proceed_record(sell2).unwrap();
+        matcher.drain_events();
+
+        // Consumes the first order completely and the second one partially
+        let fok_buy = order::Order::new(order::OrderType::Fok, order::Side::Buy, 105, 8, 3);
+        matcher.proceed_record(fok_buy).unwrap();
+        assert_eq!(
+            event_kinds(&mut matcher),
+            vec![
+                EventKind::Accepted,
+                EventKind::Executed,
+                EventKind::Executed
+            ]
+        );
+
+        // Only two units are left in the book
+        let too_large = order::Order::new(order::OrderType::Fok, order::Side::Buy, 105, 3, 4);
+        matcher.proceed_record(too_large).unwrap();
+        assert_eq!(
+            event_kinds(&mut matcher),
+            vec![EventKind::Accepted, EventKind::Canceled]
+        );
+
+        matcher.shutdown();
+        let remaining: Vec<Event> = matcher.drain_events().collect();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].kind, EventKind::PartiallyExecuted);
+        assert_eq!(remaining[0].order.current_qty(), 2);
+    }
+
+    #[test]
+    fn test_traded_notional() {
+        let mut matcher = Matcher::new();
+
+        let sell1 = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 5, 1);
+        let sell2 = order::Order::new(order::OrderType::Lim, order::Side::Sell, 101, 5, 2);
+        matcher.proceed_record(sell1).unwrap();
+        matcher.proceed_record(sell2).unwrap();
+
+        // Trades happen at the resting prices: 5 * 100 + 2 * 101
+        let buy = order::Order::new(order::OrderType::Ioc, order::Side::Buy, 105, 7, 3);
+        matcher.proceed_record(buy).unwrap();
+        assert_eq!(matcher.traded_notional(), Notional::new(1, 702));
+
+        let too_large = order::Order::new(order::OrderType::Lim, order::Side::Buy, u64::MAX, 2, 3);
+        assert_eq!(
+            matcher.proceed_record(too_large),
+            Err(MatchError::Rejected(error::RejectReason::NotionalOverflow))
+        );
+    }
+
+    fn remaining_qtys(matcher: &mut Matcher) -> Vec<(u64, u64)> {
+        matcher.drain_events();
+        matcher.shutdown();
+        matcher
+            .drain_events()
+            .map(|e| (e.order.user_id(), e.order.current_qty()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pro_rata_allocation() {
+        let instrument =
+            instrument::Instrument::default().with_allocation(Allocation::ProRata { min_qty: 0 });
+        let mut matcher = Matcher::with_instrument(instrument);
+
+        for (qty, user_id) in [(10, 1), (20, 2), (30, 3)] {
+            let sell =
+                order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, qty, user_id);
+            matcher.proceed_record(sell).unwrap();
+        }
+        let worse = order::Order::new(order::OrderType::Lim, order::Side::Sell, 101, 10, 5);
+        matcher.proceed_record(worse).unwrap();
+
+        // 25 is shared 5/8/12 between the orders at 100
+        let buy = order::Order::new(order::OrderType::Ioc, order::Side::Buy, 101, 25, 4);
+        matcher.proceed_record(buy).unwrap();
+
+        assert_eq!(
+            remaining_qtys(&mut matcher),
+            vec![(1, 5), (2, 12), (3, 18), (5, 10)]
+        );
+    }
+
+    #[test]
+    fn test_pro_rata_skips_own_orders() {
+        let instrument = instrument::Instrument::default()
+            .with_allocation(Allocation::FifoTopProRata { min_qty: 0 });
+        let mut matcher = Matcher::with_instrument(instrument);
+
+        for (qty, user_id) in [(10, 1), (20, 4), (30, 3)] {
+            let sell =
+                order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, qty, user_id);
+            matcher.proceed_record(sell).unwrap();
+        }
+        let worse = order::Order::new(order::OrderType::Lim, order::Side::Sell, 101, 10, 5);
+        matcher.proceed_record(worse).unwrap();
+
+        // The order of user 4 is left out, the others are filled completely,
+        // but the buy does not go on to the next level past its own order
+        let buy = order::Order::new(order::OrderType::Ioc, order::Side::Buy, 101, 50, 4);
+        matcher.proceed_record(buy).unwrap();
+
+        assert_eq!(remaining_qtys(&mut matcher), vec![(4, 20), (5, 10)]);
+    }
+
+    #[test]
+    fn test_fok_with_pro_rata() {
+        let instrument =
+            instrument::Instrument::default().with_allocation(Allocation::ProRata { min_qty: 0 });
+        let mut matcher = Matcher::with_instrument(instrument);
+
+        for (price, qty, user_id) in [(100, 10, 1), (100, 10, 2), (101, 10, 3)] {
+            let sell = order::Order::new(
+                order::OrderType::Lim,
+                order::Side::Sell,
+                price,
+                qty,
+                user_id,
+            );
+            matcher.proceed_record(sell).unwrap();
+        }
+
+        // Not enough at acceptable prices
+        let fok = order::Order::new(order::OrderType::Fok, order::Side::Buy, 100, 21, 4);
+        matcher.proceed_record(fok).unwrap();
+        assert_eq!(
+            event_kinds(&mut matcher),
+            vec![
+                EventKind::Accepted,
+                EventKind::Queued,
+                EventKind::Accepted,
+                EventKind::Queued,
+                EventKind::Accepted,
+                EventKind::Queued,
+                EventKind::Accepted,
+                EventKind::Canceled
+            ]
+        );
+
+        let fok = order::Order::new(order::OrderType::Fok, order::Side::Buy, 101, 25, 4);
+        matcher.proceed_record(fok).unwrap();
+        assert_eq!(remaining_qtys(&mut matcher), vec![(3, 5)]);
+    }
+
+    /// Shares the price improvement between both sides and gives priority to
+    /// the largest resting order.
+    struct MidpointSizePolicy;
+
+    impl MatchingPolicy for MidpointSizePolicy {
+        fn execution_price(&self, incoming: &order::Order, resting: &order::Order) -> u64 {
+            (incoming.price() + resting.price()) / 2
+        }
+
+        fn allocate(&self, mut qty: u64, resting: &[&order::Order]) -> Vec<u64> {
+            let mut by_size: Vec<usize> = (0..resting.len()).collect();
+            by_size.sort_by_key(|&i| std::cmp::Reverse(resting[i].current_qty()));
+            let mut fills = vec![0; resting.len()];
+            for i in by_size {
+                fills[i] = qty.min(resting[i].current_qty());
+                qty -= fills[i];
+            }
+            fills
+        }
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let mut matcher = Matcher::new().with_policy(Box::new(MidpointSizePolicy));
+
+        let small = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 5, 1);
+        let large = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 20, 2);
+        matcher.proceed_record(small).unwrap();
+        matcher.proceed_record(large).unwrap();
+
+        // The later but larger order is filled first, at (110 + 100) / 2
+        let buy = order::Order::new(order::OrderType::Ioc, order::Side::Buy, 110, 10, 3);
+        matcher.proceed_record(buy).unwrap();
+        assert_eq!(matcher.traded_notional(), Notional::new(105, 10));
+        assert_eq!(remaining_qtys(&mut matcher), vec![(1, 5), (2, 10)]);
+    }
+
+    struct OutsideLimitsPolicy;
+
+    impl MatchingPolicy for OutsideLimitsPolicy {
+        fn execution_price(&self, incoming: &order::Order, _resting: &order::Order) -> u64 {
+            incoming.price() + 1
+        }
+    }
+
+    #[test]
+    fn test_policy_price_outside_limits() {
+        let mut matcher = Matcher::new().with_policy(Box::new(OutsideLimitsPolicy));
+
+        let sell = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 5, 1);
+        matcher.proceed_record(sell).unwrap();
+        let buy = order::Order::new(order::OrderType::Ioc, order::Side::Buy, 100, 5, 2);
+        assert_eq!(
+            matcher.proceed_record(buy),
+            Err(MatchError::ExecutionPrice(101))
+        );
+    }
+
+    #[test]
+    fn test_failed_fill_undoes_the_sweep() {
+        let mut accounts = accounts::Accounts::new();
+        accounts.deposit(1, u64::MAX - 7, 1_000).unwrap();
+        accounts.deposit(2, 5, 0).unwrap();
+        accounts.deposit(3, 5, 0).unwrap();
+        let mut matcher = Matcher::new().with_accounts(accounts);
+        for user_id in [2, 3] {
+            let sell = order::Order::new(order::OrderType::Lim, order::Side::Sell, 100, 5, user_id);
+            matcher.proceed_record(sell).unwrap();
+        }
+        matcher.drain_events();
+        let before = matcher.accounts().cloned();
+
+        // The first fill of the level fits, the second overflows the base of the buyer
+        let buy = order::Order::new(order::OrderType::Lim, order::Side::Buy, 100, 10, 1);
+        assert_eq!(
+            matcher.proceed_record(buy),
+            Err(MatchError::BalanceOverflow(1))
+        );
+        let kinds: Vec<_> = matcher.drain_events().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Accepted, EventKind::Canceled]);
+        assert_eq!(matcher.drain_trades().count(), 0);
+        assert_eq!(matcher.traded_notional(), Notional::default());
+        assert_eq!(matcher.accounts().cloned(), before);
+        assert_eq!(remaining_qtys(&mut matcher), vec![(2, 5), (3, 5)]);
+    }
 }
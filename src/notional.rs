@@ -0,0 +1,65 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Value of a quantity at a price, `price * qty`, in the fixed-point units of
+/// both. It is kept in 128 bits, so a single product of two `u64` values can
+/// never overflow, while sums are checked.
+#[derive(
+    Debug, Default, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Serialize, Deserialize,
+)]
+pub struct Notional(u128);
+
+impl Notional {
+    pub const ZERO: Notional = Notional(0);
+
+    pub fn new(price: u64, qty: u64) -> Notional {
+        Notional(u128::from(price) * u128::from(qty))
+    }
+
+    pub fn value(&self) -> u128 {
+        self.0
+    }
+
+    /// The notional as `u64`, or `None` if it does not fit.
+    pub fn to_u64(&self) -> Option<u64> {
+        u64::try_from(self.0).ok()
+    }
+
+    pub fn checked_add(self, other: Notional) -> Option<Notional> {
+        self.0.checked_add(other.0).map(Notional)
+    }
+
+    pub fn checked_sub(self, other: Notional) -> Option<Notional> {
+        self.0.checked_sub(other.0).map(Notional)
+    }
+}
+
+impl fmt::Display for Notional {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_product_does_not_overflow() {
+        let n = Notional::new(u64::MAX, u64::MAX);
+        assert_eq!(n.value(), u128::from(u64::MAX) * u128::from(u64::MAX));
+        assert_eq!(n.to_u64(), None);
+        assert_eq!(Notional::new(100, 10).to_u64(), Some(1000));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let a = Notional::new(100, 10);
+        let b = Notional::new(5, 2);
+        assert_eq!(a.checked_add(b), Some(Notional::new(101, 10)));
+        assert_eq!(a.checked_sub(b), Some(Notional::new(99, 10)));
+        assert_eq!(b.checked_sub(a), None);
+        assert_eq!(Notional(u128::MAX).checked_add(Notional::new(1, 1)), None);
+    }
+}
@@ -1,33 +1,58 @@
 use std::hash::{Hash, Hasher};
 
-use strum::Display;
+use strum::{Display, EnumString};
 use uuid::Uuid;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Display, Debug, Eq, PartialEq, Copy, Clone, Deserialize)]
+use crate::error::{MatchError, RejectReason};
+use crate::notional::Notional;
+
+/// Read in any case, and also as `Limit`, `FillOrKill` or
+/// `ImmediateOrCancel`.
+#[derive(Display, EnumString, Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+#[strum(ascii_case_insensitive)]
 pub enum OrderType {
+    #[strum(to_string = "Lim", serialize = "Limit")]
     Lim,
+    #[strum(to_string = "Fok", serialize = "FillOrKill")]
     Fok,
+    #[strum(to_string = "Ioc", serialize = "ImmediateOrCancel")]
     Ioc,
 }
-#[derive(Display, Debug, Eq, PartialEq, Copy, Clone, Deserialize)]
+
+impl TryFrom<String> for OrderType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<OrderType, String> {
+        s.trim()
+            .parse()
+            .map_err(|_| format!("unknown order type `{}`", s))
+    }
+}
+
+/// Read in any case, and also as `B` or `S`.
+#[derive(Display, EnumString, Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+#[strum(ascii_case_insensitive)]
 pub enum Side {
+    #[strum(to_string = "Buy", serialize = "B")]
     Buy,
+    #[strum(to_string = "Sell", serialize = "S")]
     Sell,
 }
-#[derive(Display, Debug)]
-pub enum ExternalEvent {
-    Accepted,
-    Queued,
-}
-#[derive(Display, Debug)]
-enum InternalEvent {
-    Canceled,
-    Executed,
-    PartiallyExecuted,
+
+impl TryFrom<String> for Side {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Side, String> {
+        s.trim()
+            .parse()
+            .map_err(|_| format!("unknown side `{}`", s))
+    }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
     internal_id: Uuid,
     order_type: OrderType,
@@ -36,6 +61,10 @@ pub struct Order {
     initial_qty: u64,
     current_qty: u64,
     user_id: u64,
+    #[serde(default)]
+    session_id: Option<u64>,
+    #[serde(default)]
+    cancel_on_disconnect: bool,
 }
 
 impl Order {
@@ -54,82 +83,156 @@ impl Order {
             initial_qty: _initial_qty,
             current_qty: _initial_qty,
             user_id: _user_id,
+            session_id: None,
+            cancel_on_disconnect: false,
         }
     }
+
+    /// Tags the order with the session that entered it. With
+    /// `cancel_on_disconnect` the order is canceled when the session closes.
+    pub fn with_session(mut self, session_id: u64, cancel_on_disconnect: bool) -> Order {
+        self.session_id = Some(session_id);
+        self.cancel_on_disconnect = cancel_on_disconnect;
+        self
+    }
+}
+
+/// Where the ids of new orders come from.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum IdGenerator {
+    /// Random version 4 UUIDs, as made by `Order::new`.
+    #[default]
+    Random,
+    /// 1, 2, 3, ... as UUIDs; `next` is the id of the next order.
+    Sequential { next: u128 },
+    /// Version 4 UUIDs from a pseudo-random generator with a fixed seed.
+    Seeded { state: u64 },
+}
+
+impl IdGenerator {
+    pub fn sequential() -> IdGenerator {
+        IdGenerator::Sequential { next: 1 }
+    }
+
+    pub fn seeded(seed: u64) -> IdGenerator {
+        IdGenerator::Seeded { state: seed }
+    }
+
+    /// The next id, or `None` if orders keep the random id they were
+    /// created with.
+    pub fn next_id(&mut self) -> Option<Uuid> {
+        match self {
+            IdGenerator::Random => None,
+            IdGenerator::Sequential { next } => {
+                let id = Uuid::from_u128(*next);
+                *next += 1;
+                Some(id)
+            }
+            IdGenerator::Seeded { state } => {
+                let high = splitmix64(state);
+                let low = splitmix64(state);
+                let bytes = ((u128::from(high) << 64) | u128::from(low)).to_le_bytes();
+                Some(uuid::Builder::from_random_bytes(bytes).into_uuid())
+            }
+        }
+    }
+}
+
+/// SplitMix64: advances `state` and returns the next pseudo-random number.
+/// Seeded order ids come from it; it is public so that other tools can
+/// draw from the same generator.
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
 }
 
 impl Order {
-    pub fn print_due_external_event(&self, event: ExternalEvent) {
-        print!("{},", event);
-        self.print_order_info();
+    pub fn id(&self) -> Uuid {
+        self.internal_id
+    }
+
+    pub(crate) fn set_id(&mut self, id: Uuid) {
+        self.internal_id = id;
     }
 
     pub fn side(&self) -> Side {
-        return self.side;
+        self.side
     }
 
     pub fn price(&self) -> u64 {
-        return self.price;
+        self.price
+    }
+
+    pub fn initial_qty(&self) -> u64 {
+        self.initial_qty
     }
 
     pub fn current_qty(&self) -> u64 {
-        return self.current_qty;
+        self.current_qty
     }
 
     pub fn order_type(&self) -> OrderType {
-        return self.order_type;
+        self.order_type
     }
 
     pub fn user_id(&self) -> u64 {
-        return self.user_id;
+        self.user_id
     }
 
-    pub fn reduce_quantity(&mut self, qty: u64) {
-        if self.current_qty < qty {
-            panic!(
-                "Trying to reduce {}, but only {} is avaliable",
-                qty,
-                self.current_qty()
-            )
-        } else {
-            self.current_qty -= qty;
-        }
+    pub fn session_id(&self) -> Option<u64> {
+        self.session_id
     }
 
-    fn print_due_inernal_event(&self, event: InternalEvent) {
-        print!("{},", event);
-        self.print_order_info();
+    pub fn cancel_on_disconnect(&self) -> bool {
+        self.cancel_on_disconnect
     }
 
-    fn print_order_info(&self) {
-        println!(
-            "{},{},{},{},{}",
-            self.order_type, self.side, self.price, self.initial_qty, self.user_id
-        );
+    /// Value of the whole order at its limit price.
+    pub fn notional(&self) -> Notional {
+        Notional::new(self.price, self.initial_qty)
     }
-}
 
-impl Drop for Order {
-    fn drop(&mut self) {
-        let const_self = &(*self);
-        match const_self.order_type {
-            OrderType::Ioc | OrderType::Lim => {
-                if const_self.current_qty == 0 {
-                    const_self.print_due_inernal_event(InternalEvent::Executed)
-                } else if const_self.current_qty < const_self.initial_qty {
-                    const_self.print_due_inernal_event(InternalEvent::PartiallyExecuted)
-                } else {
-                    const_self.print_due_inernal_event(InternalEvent::Canceled)
-                }
-            }
-            OrderType::Fok => {
-                if const_self.current_qty != 0 {
-                    const_self.print_due_inernal_event(InternalEvent::Canceled)
-                } else {
-                    const_self.print_due_inernal_event(InternalEvent::Executed)
-                }
-            }
+    /// Gives the order a new price and remaining quantity. The filled part
+    /// stays, so the initial quantity changes by the same amount. The order
+    /// is left as it was if that quantity does not fit into `u64`.
+    pub(crate) fn amend(&mut self, price: u64, remaining_qty: u64) -> Result<(), RejectReason> {
+        let initial_qty = self
+            .initial_qty
+            .checked_sub(self.current_qty)
+            .and_then(|filled| filled.checked_add(remaining_qty))
+            .ok_or(RejectReason::QuantityOverflow)?;
+        self.price = price;
+        self.initial_qty = initial_qty;
+        self.current_qty = remaining_qty;
+        Ok(())
+    }
+
+    pub fn reduce_quantity(&mut self, qty: u64) -> Result<(), MatchError> {
+        if self.current_qty < qty {
+            return Err(MatchError::Overfill {
+                requested: qty,
+                available: self.current_qty,
+            });
         }
+        self.current_qty -= qty;
+        Ok(())
+    }
+
+    /// Checks that the order makes sense before it is accepted by the engine.
+    pub fn validate(&self) -> Result<(), RejectReason> {
+        if self.initial_qty == 0 {
+            return Err(RejectReason::ZeroQuantity);
+        }
+        if self.price == 0 {
+            return Err(RejectReason::ZeroPrice);
+        }
+        if self.notional().to_u64().is_none() {
+            return Err(RejectReason::NotionalOverflow);
+        }
+        Ok(())
     }
 }
 
@@ -157,6 +260,28 @@ impl Hash for Order {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_aliases() {
+        for (s, order_type) in [
+            ("\"Lim\"", OrderType::Lim),
+            ("\"LIMIT\"", OrderType::Lim),
+            ("\"fok\"", OrderType::Fok),
+            ("\"ImmediateOrCancel\"", OrderType::Ioc),
+        ] {
+            assert_eq!(serde_json::from_str::<OrderType>(s).unwrap(), order_type);
+        }
+        for (s, side) in [
+            ("\"BUY\"", Side::Buy),
+            ("\"b\"", Side::Buy),
+            ("\"S\"", Side::Sell),
+        ] {
+            assert_eq!(serde_json::from_str::<Side>(s).unwrap(), side);
+        }
+        assert!(serde_json::from_str::<Side>("\"Bid\"").is_err());
+        assert_eq!(OrderType::Lim.to_string(), "Lim");
+        assert_eq!(serde_json::to_string(&Side::Sell).unwrap(), "\"Sell\"");
+    }
+
     #[test]
     fn test_order_creation() {
         let order = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
@@ -171,12 +296,44 @@ mod tests {
     #[test]
     fn test_reduce_quantity() {
         let mut order = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
-        order.reduce_quantity(3);
+        order.reduce_quantity(3).unwrap();
         assert_eq!(order.current_qty(), 7);
-        order.reduce_quantity(7);
+        order.reduce_quantity(7).unwrap();
         assert_eq!(order.current_qty(), 0);
     }
 
+    #[test]
+    fn test_reduce_quantity_over_available() {
+        let mut order = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        assert_eq!(
+            order.reduce_quantity(11),
+            Err(MatchError::Overfill {
+                requested: 11,
+                available: 10
+            })
+        );
+        assert_eq!(order.current_qty(), 10);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Order::new(OrderType::Lim, Side::Buy, 100, 10, 1)
+            .validate()
+            .is_ok());
+        assert_eq!(
+            Order::new(OrderType::Lim, Side::Buy, 100, 0, 1).validate(),
+            Err(RejectReason::ZeroQuantity)
+        );
+        assert_eq!(
+            Order::new(OrderType::Ioc, Side::Sell, 0, 10, 1).validate(),
+            Err(RejectReason::ZeroPrice)
+        );
+        assert_eq!(
+            Order::new(OrderType::Lim, Side::Buy, u64::MAX, 2, 1).validate(),
+            Err(RejectReason::NotionalOverflow)
+        );
+    }
+
     #[test]
     fn test_eq_implementation() {
         let order1 = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
@@ -189,4 +346,21 @@ mod tests {
         let order3 = Order::new(OrderType::Fok, Side::Buy, 100, 10, 1);
         assert_ne!(order1, order3);
     }
+
+    #[test]
+    fn test_id_generators() {
+        assert_eq!(IdGenerator::Random.next_id(), None);
+
+        let mut sequential = IdGenerator::sequential();
+        assert_eq!(sequential.next_id(), Some(Uuid::from_u128(1)));
+        assert_eq!(sequential.next_id(), Some(Uuid::from_u128(2)));
+
+        let mut first = IdGenerator::seeded(42);
+        let mut second = IdGenerator::seeded(42);
+        let ids: Vec<Option<Uuid>> = (0..3).map(|_| first.next_id()).collect();
+        assert_eq!(ids, (0..3).map(|_| second.next_id()).collect::<Vec<_>>());
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[0].unwrap().get_version_num(), 4);
+        assert_ne!(IdGenerator::seeded(43).next_id(), ids[0]);
+    }
 }
@@ -0,0 +1,402 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+use strum::EnumString;
+use uuid::Uuid;
+
+use crate::error::RejectReason;
+use crate::event::{Event, EventKind};
+use crate::instrument::{format_fixed_signed, Instrument};
+use crate::order::{OrderType, Side};
+use crate::trade::Trade;
+
+/// How `EventWriter` writes events.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Format {
+    /// The lines of `Event::to_line`. Trades are not written.
+    #[default]
+    Text,
+    /// One JSON object per event or trade, told apart by its `type`.
+    Jsonl,
+    /// CSV with a header row and the columns of `CsvRecord`, the same for
+    /// events and trades.
+    Csv,
+}
+
+/// An order event as written in the structured formats. Prices, quantities
+/// and fees are decimal strings, so no precision is lost.
+#[derive(Serialize)]
+struct EventRecord {
+    seq: u64,
+    #[serde(rename = "type")]
+    kind: EventKind,
+    order_id: Uuid,
+    user_id: u64,
+    order_type: OrderType,
+    side: Side,
+    price: String,
+    initial_qty: String,
+    remaining_qty: String,
+    filled_qty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<RejectReason>,
+}
+
+#[derive(Serialize)]
+struct TradeRecord {
+    seq: u64,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    price: String,
+    qty: String,
+    taker_side: Side,
+    taker_order_id: Uuid,
+    taker_user_id: u64,
+    maker_order_id: Uuid,
+    maker_user_id: u64,
+    taker_fee: String,
+    maker_fee: String,
+}
+
+/// A row of the CSV format. Columns that do not apply to a row are left
+/// empty: the trade columns for order events, and the order columns other
+/// than the id, user and side of the taker for trades.
+#[derive(Default, Serialize)]
+struct CsvRecord {
+    seq: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    order_id: Option<Uuid>,
+    user_id: Option<u64>,
+    order_type: Option<OrderType>,
+    side: Option<Side>,
+    price: Option<String>,
+    initial_qty: Option<String>,
+    remaining_qty: Option<String>,
+    filled_qty: Option<String>,
+    reason: Option<RejectReason>,
+    trade_price: Option<String>,
+    trade_qty: Option<String>,
+    maker_order_id: Option<Uuid>,
+    maker_user_id: Option<u64>,
+    taker_fee: Option<String>,
+    maker_fee: Option<String>,
+}
+
+const CSV_HEADER: [&str; 17] = [
+    "seq",
+    "type",
+    "order_id",
+    "user_id",
+    "order_type",
+    "side",
+    "price",
+    "initial_qty",
+    "remaining_qty",
+    "filled_qty",
+    "reason",
+    "trade_price",
+    "trade_qty",
+    "maker_order_id",
+    "maker_user_id",
+    "taker_fee",
+    "maker_fee",
+];
+
+enum Sink<W: Write> {
+    Plain(W),
+    Csv {
+        writer: Box<csv::Writer<W>>,
+        header_written: bool,
+    },
+}
+
+/// Writes the output of a matcher in one of the supported formats, with
+/// values scaled for its instrument.
+pub struct EventWriter<W: Write> {
+    out: Sink<W>,
+    format: Format,
+    instrument: Instrument,
+}
+
+impl<W: Write> EventWriter<W> {
+    pub fn new(out: W, format: Format, instrument: Instrument) -> EventWriter<W> {
+        let out = match format {
+            Format::Csv => Sink::Csv {
+                writer: Box::new(
+                    csv::WriterBuilder::new()
+                        .has_headers(false)
+                        .from_writer(out),
+                ),
+                header_written: false,
+            },
+            Format::Text | Format::Jsonl => Sink::Plain(out),
+        };
+        EventWriter {
+            out,
+            format,
+            instrument,
+        }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        match self.out {
+            Sink::Plain(out) => Ok(out),
+            Sink::Csv { writer, .. } => writer.into_inner().map_err(|e| e.into_error()),
+        }
+    }
+
+    /// Writes events and trades drained from a matcher, merged back into
+    /// the order they happened in.
+    pub fn write(&mut self, events: &[Event], trades: &[Trade]) -> io::Result<()> {
+        let mut events = events.iter().peekable();
+        let mut trades = trades.iter().peekable();
+        loop {
+            let event_first = match (events.peek(), trades.peek()) {
+                (Some(event), Some(trade)) => event.seq < trade.seq,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return Ok(()),
+            };
+            if event_first {
+                self.write_event(events.next().unwrap())?;
+            } else {
+                self.write_trade(trades.next().unwrap())?;
+            }
+        }
+    }
+
+    pub fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        match self.format {
+            Format::Text => {
+                let line = event.to_line(&self.instrument);
+                writeln!(self.plain(), "{}", line)
+            }
+            Format::Jsonl => {
+                let record = self.event_record(event);
+                self.write_json(&record)
+            }
+            Format::Csv => {
+                let record = self.event_record(event);
+                self.write_csv(CsvRecord {
+                    seq: record.seq,
+                    kind: record.kind.to_string(),
+                    order_id: Some(record.order_id),
+                    user_id: Some(record.user_id),
+                    order_type: Some(record.order_type),
+                    side: Some(record.side),
+                    price: Some(record.price),
+                    initial_qty: Some(record.initial_qty),
+                    remaining_qty: Some(record.remaining_qty),
+                    filled_qty: Some(record.filled_qty),
+                    reason: record.reason,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    pub fn write_trade(&mut self, trade: &Trade) -> io::Result<()> {
+        match self.format {
+            Format::Text => Ok(()),
+            Format::Jsonl => {
+                let record = self.trade_record(trade);
+                self.write_json(&record)
+            }
+            Format::Csv => {
+                let record = self.trade_record(trade);
+                self.write_csv(CsvRecord {
+                    seq: record.seq,
+                    kind: record.kind.to_string(),
+                    order_id: Some(record.taker_order_id),
+                    user_id: Some(record.taker_user_id),
+                    side: Some(record.taker_side),
+                    trade_price: Some(record.price),
+                    trade_qty: Some(record.qty),
+                    maker_order_id: Some(record.maker_order_id),
+                    maker_user_id: Some(record.maker_user_id),
+                    taker_fee: Some(record.taker_fee),
+                    maker_fee: Some(record.maker_fee),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    /// Flushes the output. A CSV output gets its header row here at the
+    /// latest, even if nothing was written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.out {
+            Sink::Plain(out) => out.flush(),
+            Sink::Csv { .. } => self.csv()?.flush(),
+        }
+    }
+
+    fn plain(&mut self) -> &mut W {
+        match &mut self.out {
+            Sink::Plain(out) => out,
+            Sink::Csv { .. } => unreachable!("only the CSV format writes through csv"),
+        }
+    }
+
+    fn write_json<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        let out = self.plain();
+        serde_json::to_writer(&mut *out, record)?;
+        writeln!(out)
+    }
+
+    /// The CSV writer, after writing the header row if still needed.
+    fn csv(&mut self) -> io::Result<&mut csv::Writer<W>> {
+        match &mut self.out {
+            Sink::Plain(_) => Err(io::Error::other("not a CSV output")),
+            Sink::Csv {
+                writer,
+                header_written,
+            } => {
+                if !*header_written {
+                    writer.write_record(CSV_HEADER)?;
+                    *header_written = true;
+                }
+                Ok(writer)
+            }
+        }
+    }
+
+    fn write_csv(&mut self, record: CsvRecord) -> io::Result<()> {
+        self.csv()?.serialize(record)?;
+        Ok(())
+    }
+
+    fn event_record(&self, event: &Event) -> EventRecord {
+        let o = &event.order;
+        EventRecord {
+            seq: event.seq,
+            kind: event.kind,
+            order_id: o.id(),
+            user_id: o.user_id(),
+            order_type: o.order_type(),
+            side: o.side(),
+            price: self.instrument.format_price(o.price()),
+            initial_qty: self.instrument.format_qty(o.initial_qty()),
+            remaining_qty: self.instrument.format_qty(o.current_qty()),
+            filled_qty: self
+                .instrument
+                .format_qty(o.initial_qty() - o.current_qty()),
+            reason: event.reason,
+        }
+    }
+
+    fn trade_record(&self, trade: &Trade) -> TradeRecord {
+        // Fees are notionals, with the decimal places of price and quantity
+        let fee_scale = self.instrument.price_scale() + self.instrument.qty_scale();
+        TradeRecord {
+            seq: trade.seq,
+            kind: "Trade",
+            price: self.instrument.format_price(trade.price),
+            qty: self.instrument.format_qty(trade.qty),
+            taker_side: trade.taker_side,
+            taker_order_id: trade.taker_order_id,
+            taker_user_id: trade.taker_user_id,
+            maker_order_id: trade.maker_order_id,
+            maker_user_id: trade.maker_user_id,
+            taker_fee: format_fixed_signed(trade.taker_fee, fee_scale),
+            maker_fee: format_fixed_signed(trade.maker_fee, fee_scale),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{IdGenerator, Order};
+    use crate::Matcher;
+
+    fn run(format: Format) -> String {
+        let instrument = Instrument::new(2, 0).unwrap();
+        let mut matcher = Matcher::with_instrument(instrument).with_ids(IdGenerator::sequential());
+        let orders = [
+            Order::new(OrderType::Lim, Side::Sell, 10050, 5, 1),
+            Order::new(OrderType::Ioc, Side::Buy, 10100, 8, 2),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+        let events: Vec<Event> = matcher.drain_events().collect();
+        let trades: Vec<Trade> = matcher.drain_trades().collect();
+
+        let mut writer = EventWriter::new(Vec::new(), format, instrument);
+        writer.write(&events, &trades).unwrap();
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_text_format() {
+        assert_eq!(
+            run(Format::Text),
+            "Accepted,Lim,Sell,100.50,5,1\n\
+             Queued,Lim,Sell,100.50,5,1\n\
+             Accepted,Ioc,Buy,101.00,8,2\n\
+             Executed,Lim,Sell,100.50,5,1\n\
+             PartiallyExecuted,Ioc,Buy,101.00,8,2\n"
+        );
+    }
+
+    #[test]
+    fn test_jsonl_format() {
+        let lines: Vec<serde_json::Value> = run(Format::Jsonl)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<&str> = lines.iter().map(|l| l["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "Accepted",
+                "Queued",
+                "Accepted",
+                "Trade",
+                "Executed",
+                "PartiallyExecuted"
+            ]
+        );
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["seq"], i as u64 + 1);
+        }
+
+        let trade = &lines[3];
+        assert_eq!(trade["price"], "100.50");
+        assert_eq!(trade["qty"], "5");
+        assert_eq!(trade["taker_order_id"], Uuid::from_u128(2).to_string());
+        assert_eq!(trade["maker_user_id"], 1);
+
+        let partial = &lines[5];
+        assert_eq!(partial["order_id"], Uuid::from_u128(2).to_string());
+        assert_eq!(partial["side"], "Buy");
+        assert_eq!(partial["initial_qty"], "8");
+        assert_eq!(partial["remaining_qty"], "3");
+        assert_eq!(partial["filled_qty"], "5");
+        assert!(partial.get("reason").is_none());
+    }
+
+    #[test]
+    fn test_csv_format() {
+        assert_eq!(
+            run(Format::Csv),
+            "seq,type,order_id,user_id,order_type,side,price,initial_qty,remaining_qty,filled_qty,reason,trade_price,trade_qty,maker_order_id,maker_user_id,taker_fee,maker_fee\n\
+             1,Accepted,00000000-0000-0000-0000-000000000001,1,Lim,Sell,100.50,5,5,0,,,,,,,\n\
+             2,Queued,00000000-0000-0000-0000-000000000001,1,Lim,Sell,100.50,5,5,0,,,,,,,\n\
+             3,Accepted,00000000-0000-0000-0000-000000000002,2,Ioc,Buy,101.00,8,8,0,,,,,,,\n\
+             4,Trade,00000000-0000-0000-0000-000000000002,2,,Buy,,,,,,100.50,5,00000000-0000-0000-0000-000000000001,1,0.00,0.00\n\
+             5,Executed,00000000-0000-0000-0000-000000000001,1,Lim,Sell,100.50,5,0,5,,,,,,,\n\
+             6,PartiallyExecuted,00000000-0000-0000-0000-000000000002,2,Ioc,Buy,101.00,8,3,5,,,,,,,\n"
+        );
+
+        let empty = EventWriter::new(Vec::new(), Format::Csv, Instrument::default());
+        let header = String::from_utf8(empty.into_inner().unwrap()).unwrap();
+        assert_eq!(header.lines().count(), 1);
+        assert!(header.starts_with("seq,type,order_id,"));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::allocation::Allocation;
+use crate::order::{Order, Side};
+
+/// What a policy decides about a resting order met while matching.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Eligibility {
+    /// The order takes part in the allocation of its level.
+    Trade,
+    /// The order is left out, but the rest of its level can still trade.
+    /// Matching does not continue to worse levels past it.
+    Skip,
+    /// Matching stops at this order.
+    Stop,
+}
+
+/// Rules used by `Matcher` to execute an incoming order against the book.
+/// Every method has a default implementing plain price-time priority, so an
+/// implementation only overrides what it wants to change.
+pub trait MatchingPolicy {
+    /// Decides whether `resting`, which is on the other side of the book,
+    /// may trade with `incoming`. Orders of the same user never trade.
+    fn eligibility(&self, incoming: &Order, resting: &Order) -> Eligibility {
+        if incoming.user_id() == resting.user_id() || !prices_cross(incoming, resting) {
+            return Eligibility::Stop;
+        }
+        Eligibility::Trade
+    }
+
+    /// Price at which `incoming` trades with `resting`. It must lie within
+    /// the limits of both orders.
+    fn execution_price(&self, _incoming: &Order, resting: &Order) -> u64 {
+        resting.price()
+    }
+
+    /// Splits `qty` between the eligible orders of one level, listed in time
+    /// priority. Returns the quantity to fill for each of them; all of `qty`
+    /// should be handed out as long as the level has enough, otherwise a
+    /// fill-or-kill order may end up partially executed.
+    fn allocate(&self, qty: u64, resting: &[&Order]) -> Vec<u64> {
+        let available: Vec<u64> = resting.iter().map(|o| o.current_qty()).collect();
+        Allocation::Fifo.allocate(qty, &available)
+    }
+}
+
+/// Whether the limit prices of two opposite orders allow them to trade.
+pub fn prices_cross(lhs: &Order, rhs: &Order) -> bool {
+    let (buy, sell) = if lhs.side() == Side::Buy {
+        (lhs.price(), rhs.price())
+    } else {
+        (rhs.price(), lhs.price())
+    };
+    buy >= sell
+}
+
+/// The policy used by default: trades at the resting price and allocates
+/// within a level as configured for the instrument. Under pro-rata the
+/// orders of the incoming user are left out of the allocation instead of
+/// stopping it.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StandardPolicy {
+    allocation: Allocation,
+}
+
+impl StandardPolicy {
+    pub fn new(allocation: Allocation) -> StandardPolicy {
+        StandardPolicy { allocation }
+    }
+}
+
+impl MatchingPolicy for StandardPolicy {
+    fn eligibility(&self, incoming: &Order, resting: &Order) -> Eligibility {
+        if !prices_cross(incoming, resting) {
+            return Eligibility::Stop;
+        }
+        if incoming.user_id() == resting.user_id() {
+            if self.allocation == Allocation::Fifo {
+                return Eligibility::Stop;
+            }
+            return Eligibility::Skip;
+        }
+        Eligibility::Trade
+    }
+
+    fn allocate(&self, qty: u64, resting: &[&Order]) -> Vec<u64> {
+        let available: Vec<u64> = resting.iter().map(|o| o.current_qty()).collect();
+        self.allocation.allocate(qty, &available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::OrderType;
+
+    #[test]
+    fn test_standard_eligibility() {
+        let buy = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        let cheap = Order::new(OrderType::Lim, Side::Sell, 99, 10, 2);
+        let expensive = Order::new(OrderType::Lim, Side::Sell, 101, 10, 2);
+        let own = Order::new(OrderType::Lim, Side::Sell, 99, 10, 1);
+
+        let fifo = StandardPolicy::default();
+        assert_eq!(fifo.eligibility(&buy, &cheap), Eligibility::Trade);
+        assert_eq!(fifo.eligibility(&buy, &expensive), Eligibility::Stop);
+        assert_eq!(fifo.eligibility(&buy, &own), Eligibility::Stop);
+
+        let pro_rata = StandardPolicy::new(Allocation::ProRata { min_qty: 0 });
+        assert_eq!(pro_rata.eligibility(&buy, &own), Eligibility::Skip);
+        assert_eq!(pro_rata.eligibility(&buy, &expensive), Eligibility::Stop);
+    }
+
+    #[test]
+    fn test_default_execution_price() {
+        let buy = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1);
+        let sell = Order::new(OrderType::Lim, Side::Sell, 95, 10, 2);
+        assert_eq!(StandardPolicy::default().execution_price(&buy, &sell), 95);
+        assert_eq!(StandardPolicy::default().execution_price(&sell, &buy), 100);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+
+use crate::order::Side;
+use crate::trade::Trade;
+use crate::Matcher;
+
+/// Net holdings of one user built from its trades. `qty` is positive when
+/// long and negative when short; `cost` is the signed value paid for the
+/// open quantity, in notional units. Realized PnL is booked whenever a
+/// trade reduces the position, against its average entry price. Fees are
+/// kept apart from the PnL.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub qty: i128,
+    pub cost: i128,
+    pub realized_pnl: i128,
+    /// Total fees paid, net of rebates.
+    #[serde(default)]
+    pub fees: i128,
+}
+
+impl Position {
+    /// Applies a trade of `qty` at `price`, bought or sold.
+    fn apply(&mut self, side: Side, qty: u64, price: u64) {
+        let sign = if side == Side::Buy { 1 } else { -1 };
+        let mut qty = i128::from(qty);
+        let price = i128::from(price);
+        if self.qty.signum() == -sign {
+            let closing = qty.min(self.qty.abs());
+            let closed_cost = self.cost * closing / self.qty.abs();
+            self.realized_pnl += -sign * closing * price - closed_cost;
+            self.cost -= closed_cost;
+            self.qty += sign * closing;
+            qty -= closing;
+        }
+        self.qty += sign * qty;
+        self.cost += sign * qty * price;
+    }
+
+    /// Average entry price of the open quantity, rounded down to whole price
+    /// units, or `None` when flat.
+    pub fn average_price(&self) -> Option<u64> {
+        if self.qty == 0 {
+            return None;
+        }
+        u64::try_from(self.cost.abs() / self.qty.abs()).ok()
+    }
+
+    /// Profit or loss of the open quantity if it were closed at `mark`.
+    pub fn unrealized_pnl(&self, mark: u64) -> i128 {
+        self.qty * i128::from(mark) - self.cost
+    }
+}
+
+/// Which price open positions are valued at.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Mark {
+    /// Price of the last trade.
+    #[default]
+    Last,
+    /// Middle of the best bid and ask, rounded down; the last trade price
+    /// while one side of the book is empty.
+    Mid,
+}
+
+/// Positions of all users that traded, together with the last trade price.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Positions {
+    positions: BTreeMap<u64, Position>,
+    last_price: Option<u64>,
+}
+
+impl Positions {
+    /// The position of a user; users who never traded are flat.
+    pub fn get(&self, user_id: u64) -> Position {
+        self.positions.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// All users that traded, by user id.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Position)> {
+        self.positions
+            .iter()
+            .map(|(&user_id, position)| (user_id, position))
+    }
+
+    pub fn last_price(&self) -> Option<u64> {
+        self.last_price
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, trade: &Trade) {
+        let buyer = self.positions.entry(trade.buyer()).or_default();
+        buyer.apply(Side::Buy, trade.qty, trade.price);
+        let seller = self.positions.entry(trade.seller()).or_default();
+        seller.apply(Side::Sell, trade.qty, trade.price);
+
+        self.positions.entry(trade.taker_user_id).or_default().fees += trade.taker_fee;
+        self.positions.entry(trade.maker_user_id).or_default().fees += trade.maker_fee;
+        self.last_price = Some(trade.price);
+    }
+}
+
+impl Matcher {
+    pub fn positions(&self) -> &Positions {
+        &self.positions
+    }
+
+    /// Current price to value positions at, `None` before the first trade.
+    pub fn mark_price(&self, mark: Mark) -> Option<u64> {
+        let last = self.positions.last_price();
+        match mark {
+            Mark::Last => last,
+            Mark::Mid => {
+                let bid = self.g.levels(Side::Buy).next().map(|(price, _)| price);
+                let ask = self.g.levels(Side::Sell).next().map(|(price, _)| price);
+                match (bid, ask) {
+                    (Some(bid), Some(ask)) => Some(bid / 2 + ask / 2 + (bid % 2 + ask % 2) / 2),
+                    _ => last,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{Order, OrderType};
+
+    #[test]
+    fn test_position_round_trip() {
+        let mut position = Position::default();
+        position.apply(Side::Buy, 10, 100);
+        position.apply(Side::Buy, 10, 110);
+        assert_eq!(position.qty, 20);
+        assert_eq!(position.average_price(), Some(105));
+        assert_eq!(position.unrealized_pnl(120), 300);
+
+        // Selling 5 at 120 realizes 5 * (120 - 105)
+        position.apply(Side::Sell, 5, 120);
+        assert_eq!(position.realized_pnl, 75);
+        assert_eq!(position.average_price(), Some(105));
+
+        // Selling 25 at 90 closes the 15 left and opens a short of 10
+        position.apply(Side::Sell, 25, 90);
+        assert_eq!(position.realized_pnl, 75 - 15 * 15);
+        assert_eq!(position.qty, -10);
+        assert_eq!(position.average_price(), Some(90));
+        assert_eq!(position.unrealized_pnl(80), 100);
+
+        position.apply(Side::Buy, 10, 95);
+        assert_eq!(position.realized_pnl, 75 - 225 - 50);
+        assert_eq!(
+            position,
+            Position {
+                qty: 0,
+                cost: 0,
+                realized_pnl: -200,
+                fees: 0
+            }
+        );
+        assert_eq!(position.average_price(), None);
+    }
+
+    #[test]
+    fn test_matcher_tracks_positions() {
+        let mut matcher = Matcher::new();
+        assert_eq!(matcher.mark_price(Mark::Last), None);
+
+        let orders = [
+            Order::new(OrderType::Lim, Side::Sell, 100, 10, 1),
+            Order::new(OrderType::Lim, Side::Buy, 101, 4, 2),
+            Order::new(OrderType::Lim, Side::Buy, 97, 5, 3),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+
+        assert_eq!(matcher.positions().get(1).qty, -4);
+        assert_eq!(matcher.positions().get(2).qty, 4);
+        assert_eq!(matcher.positions().get(3), Position::default());
+        assert_eq!(matcher.mark_price(Mark::Last), Some(100));
+        // Best bid 97, best ask 100
+        assert_eq!(matcher.mark_price(Mark::Mid), Some(98));
+        assert_eq!(matcher.positions().get(1).unrealized_pnl(98), 8);
+    }
+}
@@ -0,0 +1,198 @@
+//! The `repl` subcommand of the `matcher` binary: commands typed one per
+//! line are applied to a live matcher, and their events printed right away.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+
+use matcher::command::Command;
+use matcher::error::MatchError;
+use matcher::input::{DecimalText, JsonCommand, OrderBuilder, RecordType};
+use matcher::instrument::Instrument;
+use matcher::order::{OrderType, Side};
+use matcher::output::{EventWriter, Format};
+use matcher::Matcher;
+
+use super::{print_book, Output};
+
+const HELP: &str = "\
+new <type> <side> <price> <qty> <user>         enter an order, e.g. `new lim buy 100 10 1`
+cancel <order_id> <user>                       cancel a resting order
+amend <order_id> <user> [price=<p>] [qty=<q>]  change the price or quantity of an order
+mass-cancel <user> [side]                      cancel the resting orders of a user
+book                                           price levels of both sides
+bbo                                            best bid and offer
+orders <user>                                  resting orders of a user
+save <file>                                    save the state to a snapshot
+load <file>                                    continue from a snapshot
+help                                           this text
+quit                                           leave";
+
+/// Reads commands from stdin until it ends or `quit` is typed. Mistakes are
+/// reported and the session goes on.
+pub fn run(mut matcher: Matcher) -> Result<(), Box<dyn Error>> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut output = output(&matcher);
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["quit" | "exit"] => break,
+            ["help"] => println!("{}", HELP),
+            ["book"] => print_book(&matcher),
+            ["bbo"] => print_bbo(&matcher),
+            ["orders", user_id] => match user_id.parse() {
+                Ok(user_id) => print_orders(&matcher, user_id),
+                Err(e) => eprintln!("error: user {}", e),
+            },
+            ["save", path] => {
+                let saved = File::create(path).map_err(|e| e.to_string()).and_then(|f| {
+                    matcher
+                        .save_snapshot(BufWriter::new(f))
+                        .map_err(|e| e.to_string())
+                });
+                if let Err(e) = saved {
+                    eprintln!("error: {}", e);
+                }
+            }
+            ["load", path] => {
+                let loaded = File::open(path).map_err(|e| e.to_string()).and_then(|f| {
+                    Matcher::load_snapshot(BufReader::new(f)).map_err(|e| e.to_string())
+                });
+                match loaded {
+                    Ok(loaded) => {
+                        matcher = loaded;
+                        output = self::output(&matcher);
+                    }
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            _ => match parse_command(&words, matcher.instrument()) {
+                Ok(command) => {
+                    let result = matcher.execute(command);
+                    output.write(&mut matcher)?;
+                    match result {
+                        Ok(()) => {}
+                        Err(MatchError::Rejected(reason)) => eprintln!("rejected: {}", reason),
+                        Err(e) if e.is_refusal() => eprintln!("refused: {}", e),
+                        Err(e) => eprintln!("error: {}", e),
+                    }
+                }
+                Err(e) => eprintln!("error: {}", e),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn output(matcher: &Matcher) -> Output {
+    Output {
+        events: EventWriter::new(
+            BufWriter::new(io::stdout()),
+            Format::Text,
+            *matcher.instrument(),
+        ),
+        trades: None,
+        flush_each: true,
+    }
+}
+
+/// Order commands are checked like the records of an input file.
+fn parse_command(words: &[&str], instrument: &Instrument) -> Result<Command, String> {
+    let user = |s: &str| s.parse::<u64>().map_err(|e| format!("user {}", e));
+    let order_id = |s: &str| s.parse().map_err(|e| format!("order_id {}", e));
+    match words {
+        ["new", order_type, side, price, qty, user_id] => OrderBuilder {
+            order_type: RecordType::Order(
+                order_type
+                    .parse::<OrderType>()
+                    .map_err(|_| format!("unknown order type `{}`", order_type))?,
+            ),
+            side: Some(parse_side(side)?),
+            price: price.to_string(),
+            initial_qty: qty.to_string(),
+            user_id: user(user_id)?,
+            session_id: None,
+            cancel_on_disconnect: None,
+        }
+        .build(instrument),
+        ["cancel", id, user_id] => Ok(Command::Cancel {
+            order_id: order_id(id)?,
+            user_id: user(user_id)?,
+        }),
+        ["amend", id, user_id, changes @ ..] => {
+            let (mut price, mut qty) = (None, None);
+            for change in changes {
+                match change.split_once('=') {
+                    Some(("price", value)) => price = Some(DecimalText(value.to_string())),
+                    Some(("qty", value)) => qty = Some(DecimalText(value.to_string())),
+                    _ => return Err(format!("expected price=<p> or qty=<q>, got `{}`", change)),
+                }
+            }
+            JsonCommand::Amend {
+                order_id: order_id(id)?,
+                user_id: user(user_id)?,
+                price,
+                qty,
+            }
+            .build(instrument)
+        }
+        ["mass-cancel", user_id, side @ ..] if side.len() <= 1 => JsonCommand::MassCancel {
+            user_id: user(user_id)?,
+            side: side.first().map(|s| parse_side(s)).transpose()?,
+            min_price: None,
+            max_price: None,
+        }
+        .build(instrument),
+        [word, ..] => Err(format!("cannot read `{}`, try `help`", word)),
+        [] => Err("empty command".to_string()),
+    }
+}
+
+fn parse_side(s: &str) -> Result<Side, String> {
+    s.parse().map_err(|_| format!("unknown side `{}`", s))
+}
+
+fn print_bbo(matcher: &Matcher) {
+    let instrument = matcher.instrument();
+    let best = |side| match matcher.book().depth(side).next() {
+        Some((price, qty, _)) => format!(
+            "{} x {}",
+            instrument.format_price(price),
+            instrument.format_qty(qty)
+        ),
+        None => "-".to_string(),
+    };
+    println!("bid {}, ask {}", best(Side::Buy), best(Side::Sell));
+}
+
+fn print_orders(matcher: &Matcher, user_id: u64) {
+    let instrument = matcher.instrument();
+    for side in [Side::Buy, Side::Sell] {
+        for o in matcher
+            .book()
+            .orders(side)
+            .filter(|o| o.user_id() == user_id)
+        {
+            println!(
+                "{} {} {} {} {}/{}",
+                o.id(),
+                o.order_type(),
+                o.side(),
+                instrument.format_price(o.price()),
+                instrument.format_qty(o.current_qty()),
+                instrument.format_qty(o.initial_qty())
+            );
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::MassCancelSummary;
+use crate::error::{MatchError, RejectReason};
+use crate::event::{Event, EventKind};
+use crate::order::{Order, Side};
+use crate::Matcher;
+
+/// A connection of a client to the engine. One user may have several
+/// sessions open at the same time.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: u64,
+}
+
+impl Matcher {
+    pub fn open_session(&mut self, session_id: u64, user_id: u64) -> Result<(), MatchError> {
+        if self.sessions.contains_key(&session_id) {
+            return Err(MatchError::SessionExists(session_id));
+        }
+        self.sessions.insert(session_id, Session { user_id });
+        Ok(())
+    }
+
+    /// Closes a session and cancels its resting orders that were entered
+    /// with cancel-on-disconnect, reporting a Canceled event for each.
+    pub fn close_session(&mut self, session_id: u64) -> Result<MassCancelSummary, MatchError> {
+        if self.sessions.remove(&session_id).is_none() {
+            return Err(MatchError::UnknownSession(session_id));
+        }
+        let mut summary = MassCancelSummary::default();
+        for side in [Side::Buy, Side::Sell] {
+            let canceled = self.g.remove_where(side, |o| {
+                o.session_id() == Some(session_id) && o.cancel_on_disconnect()
+            });
+            for o in canceled {
+                self.unlock_funds(&o);
+                summary.orders += 1;
+                summary.qty += u128::from(o.current_qty());
+                self.emit(Event::new(EventKind::Canceled, &o));
+            }
+        }
+        Ok(summary)
+    }
+
+    pub fn session(&self, session_id: u64) -> Option<&Session> {
+        self.sessions.get(&session_id)
+    }
+
+    /// An order may only be entered through an open session of its user.
+    pub(crate) fn check_session(&self, o: &Order) -> Result<(), RejectReason> {
+        match o.session_id() {
+            None => Ok(()),
+            Some(session_id) => match self.sessions.get(&session_id) {
+                Some(session) if session.user_id == o.user_id() => Ok(()),
+                Some(_) => Err(RejectReason::SessionUserMismatch),
+                None => Err(RejectReason::UnknownSession),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::OrderType;
+
+    #[test]
+    fn test_close_cancels_flagged_orders_only() {
+        let mut matcher = Matcher::new();
+        matcher.open_session(10, 1).unwrap();
+        matcher.open_session(11, 1).unwrap();
+
+        let orders = [
+            Order::new(OrderType::Lim, Side::Buy, 100, 10, 1).with_session(10, true),
+            Order::new(OrderType::Lim, Side::Buy, 99, 5, 1).with_session(10, false),
+            Order::new(OrderType::Lim, Side::Sell, 105, 5, 1).with_session(11, true),
+            Order::new(OrderType::Lim, Side::Sell, 106, 7, 1).with_session(10, true),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+        matcher.drain_events();
+
+        let summary = matcher.close_session(10).unwrap();
+        assert_eq!(summary, MassCancelSummary { orders: 2, qty: 17 });
+        let canceled: Vec<u64> = matcher
+            .drain_events()
+            .map(|e| {
+                assert_eq!(e.kind, EventKind::Canceled);
+                e.order.price()
+            })
+            .collect();
+        assert_eq!(canceled, vec![100, 106]);
+
+        // The other session of the same user is not affected
+        assert!(matcher.session(11).is_some());
+        assert_eq!(
+            matcher.close_session(10),
+            Err(MatchError::UnknownSession(10))
+        );
+    }
+
+    #[test]
+    fn test_orders_need_open_session_of_user() {
+        let mut matcher = Matcher::new();
+        matcher.open_session(10, 1).unwrap();
+        assert_eq!(
+            matcher.open_session(10, 2),
+            Err(MatchError::SessionExists(10))
+        );
+
+        let unknown = Order::new(OrderType::Lim, Side::Buy, 100, 10, 1).with_session(12, true);
+        assert_eq!(
+            matcher.proceed_record(unknown),
+            Err(MatchError::Rejected(RejectReason::UnknownSession))
+        );
+        let foreign = Order::new(OrderType::Lim, Side::Buy, 100, 10, 2).with_session(10, true);
+        assert_eq!(
+            matcher.proceed_record(foreign),
+            Err(MatchError::Rejected(RejectReason::SessionUserMismatch))
+        );
+    }
+
+    #[test]
+    fn test_close_total_beyond_u64() {
+        let mut matcher = Matcher::new();
+        matcher.open_session(10, 1).unwrap();
+        for _ in 0..3 {
+            let o =
+                Order::new(OrderType::Lim, Side::Sell, 1, u64::MAX / 2, 1).with_session(10, true);
+            matcher.proceed_record(o).unwrap();
+        }
+        let summary = matcher.close_session(10).unwrap();
+        assert_eq!(summary.qty, u128::from(u64::MAX / 2) * 3);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::Accounts;
+use crate::book::Book;
+use crate::error::SnapshotError;
+use crate::instrument::Instrument;
+use crate::notional::Notional;
+use crate::order::IdGenerator;
+use crate::position::Positions;
+use crate::session::Session;
+use crate::Matcher;
+
+/// Format version written into every snapshot. Snapshots of other versions
+/// are refused on load.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Full state of a `Matcher`: the instrument, the resting orders with their
+/// ids, remaining quantities and priority, and the running totals.
+/// Events not yet drained and the matching policy are not part of it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    instrument: Instrument,
+    traded_notional: Notional,
+    /// Journal sequence number of the last command included.
+    #[serde(default)]
+    sequence: u64,
+    #[serde(default)]
+    ids: IdGenerator,
+    #[serde(default)]
+    sessions: BTreeMap<u64, Session>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accounts: Option<Accounts>,
+    #[serde(default, skip_serializing_if = "Positions::is_empty")]
+    positions: Positions,
+    /// Sequence number of the last event or trade.
+    #[serde(default)]
+    event_seq: u64,
+    book: Book,
+}
+
+impl Matcher {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            instrument: self.instrument,
+            traded_notional: self.traded_notional,
+            sequence: self.sequence,
+            ids: self.ids,
+            sessions: self.sessions.clone(),
+            accounts: self.accounts.clone(),
+            positions: self.positions.clone(),
+            event_seq: self.event_seq,
+            book: self.g.clone(),
+        }
+    }
+
+    /// Rebuilds a matcher from a snapshot. It uses the standard policy of
+    /// the snapshot instrument, a custom one has to be set again.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Matcher, SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+        let mut matcher = Matcher::with_instrument(snapshot.instrument);
+        matcher.g = snapshot.book;
+        matcher.traded_notional = snapshot.traded_notional;
+        matcher.sequence = snapshot.sequence;
+        matcher.ids = snapshot.ids;
+        matcher.sessions = snapshot.sessions;
+        matcher.accounts = snapshot.accounts;
+        matcher.positions = snapshot.positions;
+        matcher.event_seq = snapshot.event_seq;
+        Ok(matcher)
+    }
+
+    /// A 64-bit FNV-1a hash of the snapshot encoding of the state. Equal
+    /// states give equal digests across runs and machines, so replicas and
+    /// regression tests can compare them cheaply.
+    pub fn digest(&self) -> u64 {
+        let encoded = serde_json::to_vec(&self.snapshot()).expect("snapshot is serializable");
+        encoded.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        serde_json::to_writer(&mut writer, &self.snapshot())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot<R: Read>(reader: R) -> Result<Matcher, SnapshotError> {
+        let snapshot: Snapshot = serde_json::from_reader(reader)?;
+        Matcher::from_snapshot(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::Allocation;
+    use crate::order::{Order, OrderType, Side};
+
+    fn sample_matcher() -> Matcher {
+        let instrument = Instrument::new(2, 1)
+            .unwrap()
+            .with_allocation(Allocation::ProRata { min_qty: 1 });
+        let mut matcher = Matcher::with_instrument(instrument);
+        let orders = [
+            Order::new(OrderType::Lim, Side::Buy, 100, 10, 1),
+            Order::new(OrderType::Lim, Side::Buy, 100, 20, 2),
+            Order::new(OrderType::Lim, Side::Buy, 98, 5, 3),
+            Order::new(OrderType::Lim, Side::Sell, 103, 7, 4),
+            Order::new(OrderType::Ioc, Side::Sell, 99, 6, 5),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+        matcher.drain_events();
+        matcher
+    }
+
+    #[test]
+    fn test_round_trip_is_exact() {
+        let matcher = sample_matcher();
+        let mut saved = Vec::new();
+        matcher.save_snapshot(&mut saved).unwrap();
+
+        let restored = Matcher::load_snapshot(saved.as_slice()).unwrap();
+        let mut saved_again = Vec::new();
+        restored.save_snapshot(&mut saved_again).unwrap();
+
+        assert_eq!(saved, saved_again);
+        assert_eq!(restored.instrument(), matcher.instrument());
+        assert_eq!(restored.traded_notional(), matcher.traded_notional());
+    }
+
+    #[test]
+    fn test_restored_matcher_continues_the_same() {
+        let mut original = sample_matcher();
+        let mut saved = Vec::new();
+        original.save_snapshot(&mut saved).unwrap();
+        let mut restored = Matcher::load_snapshot(saved.as_slice()).unwrap();
+
+        for matcher in [&mut original, &mut restored] {
+            let sell = Order::new(OrderType::Ioc, Side::Sell, 98, 22, 6);
+            matcher.proceed_record(sell).unwrap();
+            matcher.shutdown();
+        }
+        let lines = |m: &mut Matcher| -> Vec<String> {
+            m.drain_events()
+                .map(|e| format!("{},{}", e, e.order.current_qty()))
+                .collect()
+        };
+        assert_eq!(lines(&mut original), lines(&mut restored));
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        let mut snapshot = sample_matcher().snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            Matcher::from_snapshot(snapshot),
+            Err(SnapshotError::Version(v)) if v == SNAPSHOT_VERSION + 1
+        ));
+
+        let mut saved = Vec::new();
+        sample_matcher().save_snapshot(&mut saved).unwrap();
+        let corrupted =
+            String::from_utf8(saved)
+                .unwrap()
+                .replacen("\"side\":\"Buy\"", "\"side\":\"Sell\"", 1);
+        assert!(matches!(
+            Matcher::load_snapshot(corrupted.as_bytes()),
+            Err(SnapshotError::Format(_))
+        ));
+    }
+
+    fn deterministic_run(ids: IdGenerator) -> Matcher {
+        let mut matcher = Matcher::new().with_ids(ids);
+        for (side, price, qty, user_id) in [
+            (Side::Buy, 100, 10, 1),
+            (Side::Buy, 100, 7, 2),
+            (Side::Sell, 102, 5, 3),
+            (Side::Sell, 100, 12, 4),
+        ] {
+            let o = Order::new(OrderType::Lim, side, price, qty, user_id);
+            matcher.proceed_record(o).unwrap();
+        }
+        matcher
+    }
+
+    #[test]
+    fn test_deterministic_runs_have_equal_digests() {
+        let sequential = deterministic_run(IdGenerator::sequential());
+        assert_eq!(
+            sequential.digest(),
+            deterministic_run(IdGenerator::sequential()).digest()
+        );
+        assert_eq!(
+            deterministic_run(IdGenerator::seeded(7)).digest(),
+            deterministic_run(IdGenerator::seeded(7)).digest()
+        );
+        assert_ne!(
+            sequential.digest(),
+            deterministic_run(IdGenerator::seeded(7)).digest()
+        );
+
+        // Random ids make every run different
+        assert_ne!(
+            deterministic_run(IdGenerator::Random).digest(),
+            deterministic_run(IdGenerator::Random).digest()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The `stats` subcommand of the `matcher` binary: a summary of an output
+//! log in any of the output formats.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+
+use clap::ArgMatches;
+use matcher::instrument::{format_fixed_signed, Instrument};
+use serde::Deserialize;
+
+use super::quote_scale;
+
+/// The columns of a CSV log that the summary needs.
+#[derive(Deserialize)]
+struct CsvRow {
+    #[serde(rename = "type")]
+    kind: String,
+    trade_price: String,
+    trade_qty: String,
+}
+
+#[derive(Default)]
+struct Summary {
+    events: BTreeMap<String, u64>,
+    /// Text logs have no trades, so none are reported for them.
+    has_trades: bool,
+    trades: u64,
+    qty: u128,
+    notional: u128,
+    low: Option<u64>,
+    high: Option<u64>,
+}
+
+impl Summary {
+    fn event(&mut self, kind: &str) {
+        *self.events.entry(kind.to_string()).or_default() += 1;
+    }
+
+    fn trade(&mut self, instrument: &Instrument, price: &str, qty: &str) -> Result<(), String> {
+        let price = instrument
+            .parse_price(price)
+            .map_err(|e| format!("price {}", e))?;
+        let qty = instrument
+            .parse_qty(qty)
+            .map_err(|e| format!("qty {}", e))?;
+        self.trades += 1;
+        self.qty += u128::from(qty);
+        self.notional += u128::from(price) * u128::from(qty);
+        self.low = Some(self.low.map_or(price, |low| low.min(price)));
+        self.high = Some(self.high.map_or(price, |high| high.max(price)));
+        Ok(())
+    }
+
+    fn print(&self, instrument: &Instrument) -> Result<(), Box<dyn Error>> {
+        println!("events: {}", self.events.values().sum::<u64>());
+        for (kind, count) in &self.events {
+            println!("  {}: {}", kind, count);
+        }
+        if !self.has_trades {
+            return Ok(());
+        }
+        let amount = |value: u128, scale| -> Result<String, Box<dyn Error>> {
+            let value = i128::try_from(value).map_err(|_| "total out of range")?;
+            Ok(format_fixed_signed(value, scale))
+        };
+        println!("trades: {}", self.trades);
+        println!("  qty: {}", amount(self.qty, instrument.qty_scale())?);
+        println!(
+            "  notional: {}",
+            amount(self.notional, quote_scale(instrument)?)?
+        );
+        if let (Some(low), Some(high)) = (self.low, self.high) {
+            println!("  low: {}", instrument.format_price(low));
+            println!("  high: {}", instrument.format_price(high));
+        }
+        Ok(())
+    }
+}
+
+/// Counts the events of a log by kind, and its trades with their quantity,
+/// notional and price range. The format is recognized from the first line.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let instrument = Instrument::new(
+        *matches.get_one::<u32>("price-scale").unwrap(),
+        *matches.get_one::<u32>("qty-scale").unwrap(),
+    )?;
+    let path = matches.get_one::<String>("log").unwrap();
+    let mut log = String::new();
+    if path == "-" {
+        io::stdin().read_to_string(&mut log)?;
+    } else {
+        File::open(path)?.read_to_string(&mut log)?;
+    }
+
+    let mut summary = Summary::default();
+    let lines = log
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let first = lines.clone().next().map_or("", |(_, line)| line);
+    if first.starts_with('{') {
+        summary.has_trades = true;
+        for (index, line) in lines {
+            let at = |e: String| format!("line {}: {}", index + 1, e);
+            let value: serde_json::Value =
+                serde_json::from_str(line).map_err(|e| at(e.to_string()))?;
+            match value["type"].as_str() {
+                Some("Trade") => summary
+                    .trade(
+                        &instrument,
+                        value["price"].as_str().unwrap_or_default(),
+                        value["qty"].as_str().unwrap_or_default(),
+                    )
+                    .map_err(at)?,
+                Some(kind) => summary.event(kind),
+                None => return Err(at("no type".to_string()).into()),
+            }
+        }
+    } else if first.starts_with("seq,type,") {
+        summary.has_trades = true;
+        let mut reader = csv::Reader::from_reader(log.as_bytes());
+        for (index, row) in reader.deserialize::<CsvRow>().enumerate() {
+            let row = row?;
+            if row.kind == "Trade" {
+                summary
+                    .trade(&instrument, &row.trade_price, &row.trade_qty)
+                    .map_err(|e| format!("record {}: {}", index + 1, e))?;
+            } else {
+                summary.event(&row.kind);
+            }
+        }
+    } else {
+        for (_, line) in lines {
+            summary.event(line.split(',').next().unwrap_or_default());
+        }
+    }
+    summary.print(&instrument)
+}
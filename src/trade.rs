@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::order::{Order, Side};
+
+/// One fill between an incoming (taker) order and a resting (maker) order,
+/// with the fee charged to each side in notional units.
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    /// Numbered together with the events of the matcher, so the two streams
+    /// can be merged in the order things happened.
+    pub seq: u64,
+    pub price: u64,
+    pub qty: u64,
+    /// Side of the taker order, i.e. whether the trade was a buy or a sell
+    /// initiated by the incoming order.
+    pub taker_side: Side,
+    pub taker_order_id: Uuid,
+    pub taker_user_id: u64,
+    pub maker_order_id: Uuid,
+    pub maker_user_id: u64,
+    pub taker_fee: i128,
+    pub maker_fee: i128,
+}
+
+impl Trade {
+    pub(crate) fn new(taker: &Order, maker: &Order, price: u64, qty: u64) -> Trade {
+        Trade {
+            seq: 0,
+            price,
+            qty,
+            taker_side: taker.side(),
+            taker_order_id: taker.id(),
+            taker_user_id: taker.user_id(),
+            maker_order_id: maker.id(),
+            maker_user_id: maker.user_id(),
+            taker_fee: 0,
+            maker_fee: 0,
+        }
+    }
+
+    pub fn buyer(&self) -> u64 {
+        match self.taker_side {
+            Side::Buy => self.taker_user_id,
+            Side::Sell => self.maker_user_id,
+        }
+    }
+
+    pub fn seller(&self) -> u64 {
+        match self.taker_side {
+            Side::Buy => self.maker_user_id,
+            Side::Sell => self.taker_user_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fee::{FeeRate, FeeSchedule, FeeTier};
+    use crate::order::OrderType;
+    use crate::Matcher;
+
+    #[test]
+    fn test_trades_charge_maker_and_taker() {
+        let fees = FeeSchedule::new(FeeTier {
+            maker: FeeRate::Bps(-10),
+            taker: FeeRate::Bps(20),
+        })
+        .with_user_tier(
+            3,
+            FeeTier {
+                maker: FeeRate::PerUnit(0),
+                taker: FeeRate::PerUnit(5),
+            },
+        );
+        let mut matcher = Matcher::new().with_fees(fees);
+        let orders = [
+            Order::new(OrderType::Lim, Side::Sell, 1000, 10, 1),
+            Order::new(OrderType::Lim, Side::Sell, 1010, 10, 2),
+            Order::new(OrderType::Ioc, Side::Buy, 1010, 15, 3),
+        ];
+        for o in orders {
+            matcher.proceed_record(o).unwrap();
+        }
+
+        let trades: Vec<Trade> = matcher.drain_trades().collect();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(
+            (trades[0].price, trades[0].qty, trades[0].taker_side),
+            (1000, 10, Side::Buy)
+        );
+        assert_eq!((trades[0].buyer(), trades[0].seller()), (3, 1));
+        // Rebate of 10 bps of 10000 for the maker, 5 per unit for the taker
+        assert_eq!((trades[0].maker_fee, trades[0].taker_fee), (-10, 50));
+        assert_eq!((trades[1].maker_fee, trades[1].taker_fee), (-5, 25));
+
+        assert_eq!(matcher.positions().get(3).fees, 75);
+        assert_eq!(matcher.positions().get(1).fees, -10);
+        assert_eq!(matcher.positions().get(2).fees, -5);
+        assert_eq!(matcher.drain_trades().count(), 0);
+    }
+}
@@ -1,7 +1,38 @@
-use std::io::{BufRead, Cursor, Write};
-use std::process::Command;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 
+/// The binary under test, as built by cargo for this test run.
+fn matcher() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_matcher"))
+}
+
+/// A temporary file holding `lines`, one per line.
+fn input_file(lines: &[&str]) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    for line in lines {
+        writeln!(file, "{}", line).unwrap();
+    }
+    file
+}
+
+/// The path of a temporary file, as a command line argument.
+fn path(file: &NamedTempFile) -> &str {
+    file.path().to_str().unwrap()
+}
+
+/// Runs the binary on `input` followed by `args` and waits for it to exit.
+fn run_matcher(input: impl AsRef<Path>, args: &[&str]) -> Output {
+    matcher()
+        .arg(input.as_ref())
+        .args(args)
+        .output()
+        .expect("Failed to execute process")
+}
+
 #[test]
 fn test_cli_with_example_file() {
     let executable_path = std::env::current_dir()
@@ -110,3 +141,1322 @@ fn test_cli_with_generated_file() {
     // The sell order should match with the buy order (price and quantity compatible)
     // So we should see execution events
 }
+
+#[test]
+fn test_cli_with_decimal_prices() {
+    let temp_file = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100.25,1.5,1",
+        "Lim,Sell,100.2,0.5,2",
+    ]);
+
+    let output = run_matcher(
+        temp_file.path(),
+        &["--price-scale", "2", "--qty-scale", "1"],
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    assert!(stdout.contains("Queued,Lim,Buy,100.25,1.5,1"));
+    assert!(stdout.contains("Executed,Lim,Sell,100.20,0.5,2"));
+
+    // Without a price scale the same file is rejected as too precise
+    let output = run_matcher(temp_file.path(), &[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("price '100.25' has more than 0 decimal places"),
+        "Unexpected error: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_cli_rejects_notional_overflow() {
+    let temp_file = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,10000000000,10000000000,1",
+    ]);
+
+    let output = run_matcher(temp_file.path(), &[]);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(
+            "Error at record 1: price 10000000000 multiplied by initial_qty 10000000000 overflows"
+        ),
+        "Unexpected error: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_cli_resumes_from_snapshot() {
+    let first_day = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Lim,Sell,95,4,2",
+    ]);
+    let second_day = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Ioc,Sell,99,6,3",
+    ]);
+    let snapshot = NamedTempFile::new().unwrap();
+
+    let output = run_matcher(first_day.path(), &["--snapshot-out", path(&snapshot)]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    // The resting order is saved, not canceled
+    assert!(!stdout.contains("PartiallyExecuted,Lim,Buy,100,10,1"));
+
+    let output = run_matcher(second_day.path(), &["--snapshot-in", path(&snapshot)]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    // The remaining 6 of the first day order fill the new sell completely
+    assert!(stdout.contains("Executed,Ioc,Sell,99,6,3"));
+    assert!(stdout.contains("Executed,Lim,Buy,100,10,1"));
+}
+
+#[test]
+fn test_cli_replays_journal() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Lim,Sell,95,4,2",
+    ]);
+    let later = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Ioc,Sell,99,6,3",
+    ]);
+    let journal = NamedTempFile::new().unwrap();
+
+    // The first run is interrupted before it could save its book
+    let output = run_matcher(
+        orders.path(),
+        &["--journal", path(&journal), "--snapshot-out", "/dev/null"],
+    );
+    assert!(output.status.success());
+
+    let output = run_matcher(later.path(), &["--journal", path(&journal)]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+
+    // Events of the replayed orders are not reported again
+    assert!(!stdout.contains("Accepted,Lim,Buy,100,10,1"));
+    assert!(!stdout.contains("Lim,Sell,95,4,2"));
+    // But the book they left behind is there
+    assert!(stdout.contains("Executed,Ioc,Sell,99,6,3"));
+    assert!(stdout.contains("Executed,Lim,Buy,100,10,1"));
+
+    // Orders canceled at the end of a run stay canceled after a restart
+    let run = |line: &str| {
+        let input = input_file(&["order_type,side,price,initial_qty,user_id", line]);
+        let output = run_matcher(input.path(), &["--journal", path(&journal)]);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(run("Lim,Buy,98,5,4").contains("Canceled,Lim,Buy,98,5,4"));
+    assert!(run("Ioc,Sell,98,5,5").contains("Canceled,Ioc,Sell,98,5,5"));
+}
+
+#[test]
+fn test_cli_deterministic_digest() {
+    let example_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("example.csv");
+
+    let digest = |args: &[&str]| -> String {
+        let output = matcher()
+            .arg(&example_path)
+            .arg("--digest")
+            .args(args)
+            .output()
+            .expect("Failed to execute process");
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        stderr
+            .lines()
+            .find_map(|line| line.strip_prefix("Digest: "))
+            .expect("Digest not printed")
+            .to_string()
+    };
+
+    assert_eq!(digest(&["--deterministic"]), digest(&["--deterministic"]));
+    assert_eq!(digest(&["--seed", "5"]), digest(&["--seed", "5"]));
+    assert_ne!(digest(&["--seed", "5"]), digest(&["--seed", "6"]));
+}
+
+#[test]
+fn test_cli_mass_cancel_record() {
+    let temp_file = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Lim,Buy,99,5,1",
+        "Lim,Sell,105,5,1",
+        "Lim,Sell,106,5,2",
+        "MassCancel,,100-105,,1",
+        "MassCancel,Buy,,,2",
+    ]);
+
+    let output = run_matcher(temp_file.path(), &["--snapshot-out", "/dev/null"]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    let canceled: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Canceled"))
+        .collect();
+    assert_eq!(
+        canceled,
+        vec!["Canceled,Lim,Buy,100,10,1", "Canceled,Lim,Sell,105,5,1"]
+    );
+}
+
+#[test]
+fn test_cli_session_cancel_on_disconnect() {
+    let temp_file = input_file(&[
+        "order_type,side,price,initial_qty,user_id,session_id,cancel_on_disconnect",
+        "OpenSession,,,,1,7,",
+        "Lim,Buy,100,10,1,7,true",
+        "Lim,Buy,99,5,1,7,false",
+        "Lim,Sell,105,5,1,8,true",
+        "CloseSession,,,,1,7,",
+        "CloseSession,,,,1,7,",
+    ]);
+
+    let output = run_matcher(temp_file.path(), &["--snapshot-out", "/dev/null"]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    assert!(stdout.contains("Rejected,Lim,Sell,105,5,1,UnknownSession"));
+    let canceled: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("Canceled"))
+        .collect();
+    assert_eq!(canceled, vec!["Canceled,Lim,Buy,100,10,1"]);
+    assert!(stderr.contains("Record 6 refused: session 7 is not open"));
+}
+
+#[test]
+fn test_cli_account_balances() {
+    let balances = input_file(&["user_id,base,quote", "1,0,1000.00", "2,8,0"]);
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Sell,99.50,5,2",
+        "Lim,Buy,100.00,4,1",
+        "Lim,Sell,101.00,4,2",
+        "Lim,Buy,100.00,7,1",
+    ]);
+    let balances_out = NamedTempFile::new().unwrap();
+
+    let output = run_matcher(
+        orders.path(),
+        &[
+            "--price-scale",
+            "2",
+            "--balances",
+            path(&balances),
+            "--balances-out",
+            path(&balances_out),
+        ],
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    // User 2 has only 3 units left after locking 5, user 1 has 602.00 after
+    // paying 398.00. The rest of the first sell is released at shutdown.
+    assert!(stdout.contains("Rejected,Lim,Sell,101.00,4,2,InsufficientBalance"));
+    assert!(stdout.contains("Rejected,Lim,Buy,100.00,7,1,InsufficientBalance"));
+
+    let report = std::fs::read_to_string(balances_out.path()).unwrap();
+    assert_eq!(
+        report,
+        "user_id,base_free,base_locked,quote_free,quote_locked\n\
+         1,4,0,602.00,0.00\n\
+         2,4,0,398.00,0.00\n"
+    );
+}
+
+#[test]
+fn test_cli_positions_report() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Sell,10.00,5,1",
+        "Lim,Buy,10.00,5,2",
+        "Lim,Sell,12.50,2,2",
+        "Lim,Buy,12.50,2,3",
+        "Lim,Buy,11.00,1,4",
+        "Lim,Sell,12.00,1,4",
+    ]);
+    let positions_out = NamedTempFile::new().unwrap();
+
+    let output = run_matcher(
+        orders.path(),
+        &[
+            "--price-scale",
+            "2",
+            "--positions-out",
+            path(&positions_out),
+            "--mark",
+            "mid",
+        ],
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    // User 2 bought 5 at 10.00 and sold 2 at 12.50; the mid is 11.50
+    let report = std::fs::read_to_string(positions_out.path()).unwrap();
+    assert_eq!(
+        report,
+        "user_id,position,average_price,realized_pnl,unrealized_pnl,fees\n\
+         1,-5,10.00,0.00,-7.50,0.00\n\
+         2,3,10.00,5.00,4.50,0.00\n\
+         3,2,12.50,0.00,-2.00,0.00\n"
+    );
+}
+
+#[test]
+fn test_cli_trade_fees() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Sell,100.00,10,1",
+        "Ioc,Buy,100.00,4,2",
+        "Ioc,Buy,100.00,6,3",
+    ]);
+    let tiers = input_file(&["user_id,maker,taker", "3,0bps,0.05"]);
+    let trades_out = NamedTempFile::new().unwrap();
+    let positions_out = NamedTempFile::new().unwrap();
+
+    let output = run_matcher(
+        orders.path(),
+        &[
+            "--price-scale",
+            "2",
+            "--deterministic",
+            "--maker-fee",
+            "-1bps",
+            "--taker-fee",
+            "5bps",
+            "--fee-tiers",
+            path(&tiers),
+            "--trades-out",
+            path(&trades_out),
+            "--positions-out",
+            path(&positions_out),
+        ],
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    // 5 bps of 400.00 for user 2, 0.05 per unit for user 3, and a 1 bp
+    // rebate for the resting order of user 1 on each trade
+    let trades = std::fs::read_to_string(trades_out.path()).unwrap();
+    assert_eq!(
+        trades,
+        "taker_order_id,maker_order_id,taker_side,price,qty,taker_user_id,maker_user_id,taker_fee,maker_fee\n\
+         00000000-0000-0000-0000-000000000002,00000000-0000-0000-0000-000000000001,Buy,100.00,4,2,1,0.20,-0.04\n\
+         00000000-0000-0000-0000-000000000003,00000000-0000-0000-0000-000000000001,Buy,100.00,6,3,1,0.30,-0.06\n"
+    );
+    let positions = std::fs::read_to_string(positions_out.path()).unwrap();
+    assert!(positions.contains("\n1,-10,100.00,0.00,0.00,-0.10\n"));
+}
+
+#[test]
+fn test_cli_jsonl_output() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Ioc,Sell,99,4,2",
+        "Lim,Sell,0,4,2",
+    ]);
+
+    let output = run_matcher(orders.path(), &["--format", "jsonl", "--deterministic"]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let kinds: Vec<&str> = lines.iter().map(|l| l["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            "Accepted",
+            "Queued",
+            "Accepted",
+            "Trade",
+            "Executed",
+            "Rejected",
+            "PartiallyExecuted"
+        ]
+    );
+    let seqs: Vec<u64> = lines.iter().map(|l| l["seq"].as_u64().unwrap()).collect();
+    assert_eq!(seqs, (1..=7).collect::<Vec<u64>>());
+
+    assert_eq!(lines[3]["price"], "100");
+    assert_eq!(
+        lines[3]["maker_order_id"],
+        "00000000-0000-0000-0000-000000000001"
+    );
+    assert_eq!(lines[5]["reason"], "ZeroPrice");
+    assert_eq!(lines[6]["user_id"], 1);
+    assert_eq!(lines[6]["remaining_qty"], "6");
+    assert_eq!(lines[6]["filled_qty"], "4");
+}
+
+#[test]
+fn test_cli_csv_output() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Ioc,Sell,99,4,2",
+    ]);
+
+    let output = run_matcher(orders.path(), &["--format", "csv"]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    let mut reader = csv::Reader::from_reader(stdout.as_bytes());
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers.len(), 17);
+    let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+    let column = |row: &csv::StringRecord, name: &str| {
+        let i = headers.iter().position(|h| h == name).unwrap();
+        row[i].to_string()
+    };
+
+    let kinds: Vec<String> = rows.iter().map(|r| column(r, "type")).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            "Accepted",
+            "Queued",
+            "Accepted",
+            "Trade",
+            "Executed",
+            "PartiallyExecuted"
+        ]
+    );
+    assert_eq!(column(&rows[3], "trade_price"), "100");
+    assert_eq!(column(&rows[3], "trade_qty"), "4");
+    assert_eq!(column(&rows[3], "user_id"), "2");
+    assert_eq!(column(&rows[3], "remaining_qty"), "");
+    assert_eq!(column(&rows[5], "remaining_qty"), "6");
+    assert_eq!(column(&rows[5], "filled_qty"), "4");
+    assert_eq!(column(&rows[5], "trade_price"), "");
+}
+
+#[test]
+fn test_cli_streams_stdin() {
+    let mut child = matcher()
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute process");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let (lines_tx, lines_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            lines_tx.send(line.unwrap()).unwrap();
+        }
+    });
+    let next_line = || lines_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    // Each record is answered while the input is still open
+    writeln!(stdin, "order_type,side,price,initial_qty,user_id").unwrap();
+    writeln!(stdin, "Lim,Buy,100,10,1").unwrap();
+    stdin.flush().unwrap();
+    assert_eq!(next_line(), "Accepted,Lim,Buy,100,10,1");
+    assert_eq!(next_line(), "Queued,Lim,Buy,100,10,1");
+
+    writeln!(stdin, "Ioc,Sell,100,4,2").unwrap();
+    stdin.flush().unwrap();
+    assert_eq!(next_line(), "Accepted,Ioc,Sell,100,4,2");
+    assert_eq!(next_line(), "Executed,Ioc,Sell,100,4,2");
+
+    drop(stdin);
+    assert_eq!(next_line(), "PartiallyExecuted,Lim,Buy,100,10,1");
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_cli_jsonl_input() {
+    let commands = input_file(&[
+        r#"{"action":"new","order_type":"Lim","side":"Buy","price":"100","initial_qty":"10","user_id":1}"#,
+        r#"{"action":"new","order_type":"Lim","side":"Buy","price":"99","initial_qty":"5","user_id":2}"#,
+        "",
+        r#"{"action":"amend","order_id":"00000000-0000-0000-0000-000000000001","user_id":1,"qty":"4"}"#,
+        r#"{"action":"cancel","order_id":"00000000-0000-0000-0000-000000000002","user_id":3}"#,
+        r#"{"action":"cancel","order_id":"00000000-0000-0000-0000-000000000002","user_id":2}"#,
+        r#"{"action":"new","order_type":"Lim"}"#,
+    ]);
+
+    let output = run_matcher(
+        commands.path(),
+        &["--input-format", "jsonl", "--deterministic"],
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(stdout.contains("Amended"), "stdout: {}", stdout);
+    assert!(
+        stdout.contains("Canceled,Lim,Buy,99,5,2"),
+        "stdout: {}",
+        stdout
+    );
+    assert!(stderr.contains("Line 5 refused"), "stderr: {}", stderr);
+    assert!(stderr.contains("Error at line 7"), "stderr: {}", stderr);
+
+    // Numbers could lose precision on the way in
+    let numbers = input_file(&[
+        r#"{"action":"new","order_type":"Lim","side":"Buy","price":100.5,"initial_qty":"10","user_id":1}"#,
+    ]);
+    let output = run_matcher(numbers.path(), &["--input-format", "jsonl"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("number 100.5 must be given as a string"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_cli_command_records() {
+    let commands = input_file(&[
+        "action,order_id,order_type,side,price,qty,user_id,session_id,cancel_on_disconnect",
+        "open-session,,,,,,1,7,",
+        "new,,Lim,Buy,100,10,1,7,true",
+        "new,,Lim,Sell,105,5,2,,",
+        "amend,00000000-0000-0000-0000-000000000002,,,104,,2,,",
+        "cancel,00000000-0000-0000-0000-000000000002,,,,,2,,",
+        "new,,Lim,Sell,106,3,2,,",
+        "mass-cancel,,,Sell,100-110,,2,,",
+        "close-session,,,,,,,7,",
+        "cancel,,,,,,2,,",
+    ]);
+
+    let output = run_matcher(commands.path(), &["--deterministic"]);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        stdout,
+        "Accepted,Lim,Buy,100,10,1\n\
+         Queued,Lim,Buy,100,10,1\n\
+         Accepted,Lim,Sell,105,5,2\n\
+         Queued,Lim,Sell,105,5,2\n\
+         Amended,Lim,Sell,104,5,2\n\
+         Queued,Lim,Sell,104,5,2\n\
+         Canceled,Lim,Sell,104,5,2\n\
+         Accepted,Lim,Sell,106,3,2\n\
+         Queued,Lim,Sell,106,3,2\n\
+         Canceled,Lim,Sell,106,3,2\n\
+         Canceled,Lim,Buy,100,10,1\n"
+    );
+    assert!(
+        stderr.contains("Error at record 9: order_id is required for a Cancel record"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_cli_skips_malformed_records() {
+    let orders = input_file(&[
+        "order_type,side,price,initial_qty,user_id",
+        "Lim,Buy,100,10,1",
+        "Lim,Buy,abc,10,1",
+        "Lim,Sell,100,4",
+        "Lim,Sell,100,4,2",
+    ]);
+    let rejects = NamedTempFile::new().unwrap();
+
+    let output = run_matcher(
+        orders.path(),
+        &["--on-error=skip", "--reject-file", path(&rejects)],
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    assert_eq!(stdout.matches("\nExecuted,Lim,Sell").count(), 1);
+    assert!(stderr.contains("Skipped 2 malformed input records"));
+
+    let rejects = std::fs::read_to_string(rejects.path()).unwrap();
+    let lines: Vec<&str> = rejects.lines().collect();
+    assert_eq!(lines.len(), 3, "rejects: {}", rejects);
+    assert_eq!(lines[0], "line,error,input");
+    assert!(lines[1].starts_with("3,price "), "rejects: {}", rejects);
+    assert!(
+        lines[1].ends_with(",\"Lim,Buy,abc,10,1\""),
+        "rejects: {}",
+        rejects
+    );
+    assert!(lines[2].starts_with("4,"), "rejects: {}", rejects);
+
+    // Without skipping the first malformed record stops the run
+    let output = run_matcher(orders.path(), &[]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cli_csv_dialect() {
+    let orders = input_file(&["1;B;LIMIT;ref-1;10;100", "2;sell;ioc;ref-2;4;99"]);
+
+    let output = run_matcher(
+        orders.path(),
+        &[
+            "--delimiter",
+            ";",
+            "--no-header",
+            "--columns",
+            "user_id,side,order_type,_,initial_qty,price",
+        ],
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        output.status.success(),
+        "Program execution failed: {}",
+        stderr
+    );
+    assert!(stdout.starts_with(
+        "Accepted,Lim,Buy,100,10,1\n\
+         Queued,Lim,Buy,100,10,1\n\
+         Accepted,Ioc,Sell,99,4,2\n\
+         Executed,Ioc,Sell,99,4,2\n"
+    ));
+}
+
+#[test]
+fn test_cli_repl() {
+    let snapshot = NamedTempFile::new().unwrap();
+    let mut child = matcher()
+        .arg("repl")
+        .arg("--deterministic")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute process");
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "new lim buy 100 10 1").unwrap();
+    writeln!(stdin, "new LIMIT S 105 5 2").unwrap();
+    writeln!(stdin, "new lim buy 101 3 1").unwrap();
+    writeln!(stdin, "bbo").unwrap();
+    writeln!(stdin, "book").unwrap();
+    writeln!(stdin, "orders 1").unwrap();
+    writeln!(stdin, "save {}", snapshot.path().display()).unwrap();
+    writeln!(stdin, "cancel 00000000-0000-0000-0000-000000000001 1").unwrap();
+    writeln!(stdin, "load {}", snapshot.path().display()).unwrap();
+    writeln!(stdin, "orders 1").unwrap();
+    writeln!(stdin, "new lim buy abc 1 1").unwrap();
+    writeln!(stdin, "quit").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "stderr: {}", stderr);
+    assert_eq!(
+        stdout,
+        "Accepted,Lim,Buy,100,10,1\n\
+         Queued,Lim,Buy,100,10,1\n\
+         Accepted,Lim,Sell,105,5,2\n\
+         Queued,Lim,Sell,105,5,2\n\
+         Accepted,Lim,Buy,101,3,1\n\
+         Queued,Lim,Buy,101,3,1\n\
+         bid 101 x 3, ask 105 x 5\n\
+         Sell 105 x 5 (1 orders)\n\
+         --\n\
+         Buy 101 x 3 (1 orders)\n\
+         Buy 100 x 10 (1 orders)\n\
+         00000000-0000-0000-0000-000000000003 Lim Buy 101 3/3\n\
+         00000000-0000-0000-0000-000000000001 Lim Buy 100 10/10\n\
+         Canceled,Lim,Buy,100,10,1\n\
+         00000000-0000-0000-0000-000000000003 Lim Buy 101 3/3\n\
+         00000000-0000-0000-0000-000000000001 Lim Buy 100 10/10\n"
+    );
+    assert!(stderr.contains("error: price"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_cli_subcommands() {
+    let run = |args: &[&str], stdin: &[u8]| {
+        let mut child = matcher()
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // The same seed gives the same orders
+    let orders = run(&["gen", "--count", "50", "--seed", "7"], b"");
+    assert_eq!(orders, run(&["gen", "--count", "50", "--seed", "7"], b""));
+    assert_eq!(orders.lines().count(), 51);
+    // Prices stay in range around the largest mid
+    let max = u64::MAX.to_string();
+    let extreme = run(
+        &["gen", "--count", "50", "--mid", &max, "--depth", &max],
+        b"",
+    );
+    assert_eq!(extreme.lines().count(), 51);
+
+    let journal = NamedTempFile::new().unwrap();
+    let snapshot = NamedTempFile::new().unwrap();
+    let journal_path = path(&journal);
+    let snapshot_path = path(&snapshot);
+    let log = run(
+        &[
+            "run",
+            "--deterministic",
+            "--format",
+            "jsonl",
+            "--journal",
+            journal_path,
+            "--snapshot-out",
+            snapshot_path,
+        ],
+        orders.as_bytes(),
+    );
+    // Without a subcommand the arguments are those of `run`
+    let journal_copy = NamedTempFile::new().unwrap();
+    let implicit = run(
+        &[
+            "--deterministic",
+            "--format",
+            "jsonl",
+            "--journal",
+            path(&journal_copy),
+            "--snapshot-out",
+            "/dev/null",
+        ],
+        orders.as_bytes(),
+    );
+    assert_eq!(implicit, log);
+
+    let replayed = run(
+        &[
+            "replay",
+            journal_path,
+            "--deterministic",
+            "--format",
+            "jsonl",
+        ],
+        b"",
+    );
+    assert_eq!(replayed, log);
+
+    let stats = run(&["stats"], log.as_bytes());
+    assert!(stats.starts_with("events: "), "stats: {}", stats);
+    assert!(stats.contains("\n  Accepted: 50\n"), "stats: {}", stats);
+    assert!(stats.contains("\ntrades: "), "stats: {}", stats);
+
+    let book = run(&["snapshot", snapshot_path], b"");
+    assert!(book.contains("--\n"), "book: {}", book);
+    let resting = run(&["snapshot", snapshot_path, "--orders"], b"");
+    let mut lines = resting.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "order_id,user_id,order_type,side,price,remaining_qty,initial_qty,session_id"
+    );
+    let orders: Vec<&str> = lines.collect();
+    assert!(!orders.is_empty());
+    assert!(orders
+        .iter()
+        .all(|o| o.starts_with("00000000-0000-0000-0000-")));
+}
+
+/// A server subcommand listening on a free port, stopped when dropped so
+/// that a failing test does not leave it running.
+struct Server {
+    child: std::process::Child,
+    addr: String,
+}
+
+impl Server {
+    fn start(subcommand: &str) -> Server {
+        let mut child = matcher()
+            .args([subcommand, "--port", "0", "--deterministic"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut line = String::new();
+        stderr.read_line(&mut line).unwrap();
+        // The server goes on logging, which must not fail on a closed pipe
+        std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on ")
+            .map(str::to_string);
+        let mut server = Server {
+            child,
+            addr: String::new(),
+        };
+        match addr {
+            Some(addr) => server.addr = addr,
+            None => panic!("unexpected first line: {}", line),
+        }
+        server
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_cli_tcp_gateway() {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    let server = Server::start("serve");
+    let addr = &server.addr;
+    let timeout = Some(Duration::from_secs(5));
+
+    // A client sending lines
+    let mut lines = TcpStream::connect(addr).unwrap();
+    lines.set_read_timeout(timeout).unwrap();
+    let mut lines_in = BufReader::new(lines.try_clone().unwrap());
+    let mut read_line = || {
+        let mut line = String::new();
+        lines_in.read_line(&mut line).unwrap();
+        serde_json::from_str::<serde_json::Value>(&line).unwrap()
+    };
+    writeln!(
+        lines,
+        r#"{{"action":"open_session","session_id":7,"user_id":1}}"#
+    )
+    .unwrap();
+    writeln!(
+        lines,
+        r#"{{"action":"new","order_type":"Lim","side":"Buy","price":"100","initial_qty":"10","user_id":1}}"#
+    )
+    .unwrap();
+    assert_eq!(read_line()["type"], "Accepted");
+    assert_eq!(read_line()["type"], "Queued");
+
+    // A client sending length-prefixed frames
+    let mut frames = TcpStream::connect(addr).unwrap();
+    frames.set_read_timeout(timeout).unwrap();
+    let mut send_frame = |payload: &str| {
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload.as_bytes());
+        frames.write_all(&frame).unwrap();
+    };
+    send_frame(
+        r#"{"action":"new","order_type":"Ioc","side":"Sell","price":"99","initial_qty":"4","user_id":2}"#,
+    );
+    // Naming the right user does not make the order this client's to cancel
+    send_frame(
+        r#"{"action":"cancel","order_id":"00000000-0000-0000-0000-000000000001","user_id":1}"#,
+    );
+    // Nor do a mass cancel or the session of the first client reach it
+    send_frame(r#"{"action":"mass_cancel","user_id":1}"#);
+    send_frame(r#"{"action":"close_session","session_id":7}"#);
+    let mut frames_in = frames.try_clone().unwrap();
+    let mut read_frame = || {
+        let mut len = [0; 4];
+        frames_in.read_exact(&mut len).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+        frames_in.read_exact(&mut payload).unwrap();
+        serde_json::from_slice::<serde_json::Value>(&payload).unwrap()
+    };
+    let kinds: Vec<serde_json::Value> = (0..3).map(|_| read_frame()["type"].clone()).collect();
+    assert_eq!(kinds, vec!["Accepted", "Trade", "Executed"]);
+    assert_eq!(
+        read_frame()["message"],
+        "no resting order 00000000-0000-0000-0000-000000000001"
+    );
+    assert_eq!(read_frame()["message"], "session 7 is not open");
+
+    // The resting order belongs to the first client, which hears of the
+    // trade but not of the second client's attempts to cancel it
+    let trade = read_line();
+    assert_eq!(trade["type"], "Trade");
+    assert_eq!(
+        trade["maker_order_id"],
+        "00000000-0000-0000-0000-000000000001"
+    );
+    writeln!(
+        lines,
+        r#"{{"action":"amend","order_id":"00000000-0000-0000-0000-000000000001","user_id":1,"qty":"8"}}"#
+    )
+    .unwrap();
+    assert_eq!(read_line()["type"], "Amended");
+    assert_eq!(read_line()["type"], "Queued");
+    writeln!(
+        lines,
+        r#"{{"action":"cancel","order_id":"00000000-0000-0000-0000-000000000001","user_id":1}}"#
+    )
+    .unwrap();
+    let canceled = read_line();
+    assert_eq!(canceled["type"], "Canceled");
+    assert_eq!(canceled["remaining_qty"], "8");
+
+    // A line that never ends is cut off instead of buffered without bound
+    let mut endless = TcpStream::connect(addr).unwrap();
+    endless.set_read_timeout(timeout).unwrap();
+    let _ = endless.write_all(format!("{{{}", "x".repeat(1 << 20)).as_bytes());
+    match endless.read(&mut [0; 1]) {
+        Ok(n) => assert_eq!(n, 0),
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+}
+
+/// A client of the `fix` subcommand that numbers its messages itself.
+struct FixClient {
+    stream: std::net::TcpStream,
+    data: Vec<u8>,
+    comp_id: &'static str,
+    seq: u64,
+}
+
+impl FixClient {
+    fn connect(addr: &str, comp_id: &'static str) -> FixClient {
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        FixClient {
+            stream,
+            data: Vec::new(),
+            comp_id,
+            seq: 0,
+        }
+    }
+
+    fn send(&mut self, body: matcher::fix::Message) {
+        self.seq += 1;
+        self.send_numbered(self.seq, body);
+    }
+
+    fn send_numbered(&mut self, seq: u64, body: matcher::fix::Message) {
+        use matcher::fix::{tag, Message};
+
+        let mut message = Message::new(body.msg_type())
+            .with(tag::SENDER_COMP_ID, self.comp_id)
+            .with(tag::TARGET_COMP_ID, "MATCHER")
+            .with(tag::MSG_SEQ_NUM, seq)
+            .with(tag::SENDING_TIME, "20240102-13:45:00.000");
+        for (tag, value) in body.fields().skip(1) {
+            message.set(tag, value);
+        }
+        self.stream.write_all(&message.encode()).unwrap();
+    }
+
+    /// The next message, or `None` once the server has closed the
+    /// connection.
+    fn recv(&mut self) -> Option<matcher::fix::Message> {
+        use std::io::Read;
+
+        loop {
+            if let Some((message, used)) = matcher::fix::Message::decode(&self.data).unwrap() {
+                self.data.drain(..used);
+                return Some(message);
+            }
+            let mut chunk = [0; 4096];
+            let read = self.stream.read(&mut chunk).unwrap();
+            if read == 0 {
+                return None;
+            }
+            self.data.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    fn logon(&mut self) {
+        use matcher::fix::{tag, Message};
+
+        self.send(
+            Message::new("A")
+                .with(tag::ENCRYPT_METHOD, 0)
+                .with(tag::HEART_BT_INT, 30),
+        );
+        let logon = self.recv().unwrap();
+        assert_eq!(logon.msg_type(), "A");
+        assert_eq!(logon.get(tag::TARGET_COMP_ID), Some(self.comp_id));
+    }
+}
+
+#[test]
+fn test_cli_fix_gateway() {
+    use matcher::fix::{tag, Message};
+
+    let server = Server::start("fix");
+    let order = |cl_ord_id, account, side, qty, price, tif| {
+        Message::new("D")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ACCOUNT, account)
+            .with(tag::SYMBOL, "XYZ")
+            .with(tag::SIDE, side)
+            .with(tag::ORDER_QTY, qty)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, price)
+            .with(tag::TIME_IN_FORCE, tif)
+    };
+    let fields = |message: &Message, tags: &[u32]| -> Vec<String> {
+        tags.iter()
+            .map(|t| message.get(*t).unwrap_or("-").to_string())
+            .collect()
+    };
+    // ExecType, OrdStatus, ClOrdID, LeavesQty, CumQty
+    let report = [
+        tag::EXEC_TYPE,
+        tag::ORD_STATUS,
+        tag::CL_ORD_ID,
+        tag::LEAVES_QTY,
+        tag::CUM_QTY,
+    ];
+
+    let mut a = FixClient::connect(&server.addr, "CLIENT_A");
+    a.logon();
+    a.send(order("a1", 1, 1, 10, 100, 0));
+    let new = a.recv().unwrap();
+    assert_eq!(new.msg_type(), "8");
+    assert_eq!(fields(&new, &report), ["0", "0", "a1", "10", "0"]);
+    assert_eq!(new.get(tag::MSG_SEQ_NUM), Some("2"));
+
+    // Another session trades against the order
+    let mut b = FixClient::connect(&server.addr, "CLIENT_B");
+    b.logon();
+    b.send(order("b1", 2, 2, 4, 99, 3));
+    assert_eq!(
+        fields(&b.recv().unwrap(), &report),
+        ["0", "0", "b1", "4", "0"]
+    );
+    let fill = b.recv().unwrap();
+    assert_eq!(fields(&fill, &report), ["F", "2", "b1", "0", "4"]);
+    assert_eq!(fields(&fill, &[tag::LAST_PX, tag::LAST_QTY]), ["100", "4"]);
+    let fill = a.recv().unwrap();
+    assert_eq!(fields(&fill, &report), ["F", "1", "a1", "6", "4"]);
+    assert_eq!(fill.get(tag::AVG_PX), Some("100"));
+
+    // Only limit orders are taken
+    b.send(order("b2", 2, 2, 4, 99, 0).with(tag::ORD_TYPE, 1));
+    let rejected = b.recv().unwrap();
+    assert_eq!(fields(&rejected, &report), ["8", "8", "b2", "0", "0"]);
+    assert_eq!(rejected.get(tag::ORDER_ID), Some("NONE"));
+
+    // The new OrderQty includes the filled quantity
+    a.send(
+        Message::new("G")
+            .with(tag::ORIG_CL_ORD_ID, "a1")
+            .with(tag::CL_ORD_ID, "a2")
+            .with(tag::ORDER_QTY, 8)
+            .with(tag::PRICE, 101),
+    );
+    let replaced = a.recv().unwrap();
+    assert_eq!(fields(&replaced, &report), ["5", "1", "a2", "4", "4"]);
+    assert_eq!(replaced.get(tag::ORIG_CL_ORD_ID), Some("a1"));
+    assert_eq!(replaced.get(tag::PRICE), Some("101"));
+
+    a.send(
+        Message::new("F")
+            .with(tag::ORIG_CL_ORD_ID, "a1")
+            .with(tag::CL_ORD_ID, "a3"),
+    );
+    let reject = a.recv().unwrap();
+    assert_eq!(reject.msg_type(), "9");
+    assert_eq!(
+        fields(
+            &reject,
+            &[
+                tag::CL_ORD_ID,
+                tag::CXL_REJ_RESPONSE_TO,
+                tag::CXL_REJ_REASON
+            ]
+        ),
+        ["a3", "1", "1"]
+    );
+
+    // A gap in the numbers of the client is asked for again, and the
+    // message after it dropped
+    a.send_numbered(7, Message::new("0"));
+    let resend = a.recv().unwrap();
+    assert_eq!(resend.msg_type(), "2");
+    assert_eq!(
+        fields(&resend, &[tag::BEGIN_SEQ_NO, tag::END_SEQ_NO]),
+        ["5", "0"]
+    );
+
+    // Application messages are sent again, session messages skipped
+    a.send(
+        Message::new("2")
+            .with(tag::BEGIN_SEQ_NO, 2)
+            .with(tag::END_SEQ_NO, 0),
+    );
+    let resent: Vec<Vec<String>> = (0..5)
+        .map(|_| {
+            let message = a.recv().unwrap();
+            let mut fields = fields(
+                &message,
+                &[tag::MSG_SEQ_NUM, tag::POSS_DUP_FLAG, tag::CL_ORD_ID],
+            );
+            fields.insert(0, message.msg_type().to_string());
+            fields
+        })
+        .collect();
+    assert_eq!(
+        resent,
+        [
+            ["8", "2", "Y", "a1"],
+            ["8", "3", "Y", "a1"],
+            ["8", "4", "Y", "a2"],
+            ["9", "5", "Y", "a3"],
+            ["4", "6", "Y", "-"],
+        ]
+    );
+
+    a.send(
+        Message::new("F")
+            .with(tag::ORIG_CL_ORD_ID, "a2")
+            .with(tag::CL_ORD_ID, "a4"),
+    );
+    let canceled = a.recv().unwrap();
+    assert_eq!(fields(&canceled, &report), ["4", "4", "a4", "0", "4"]);
+    assert_eq!(canceled.get(tag::ORIG_CL_ORD_ID), Some("a2"));
+    assert_eq!(canceled.get(tag::MSG_SEQ_NUM), Some("7"));
+
+    a.send(order("a5", 1, 1, 10, 100, 0));
+    assert_eq!(
+        fields(&a.recv().unwrap(), &report),
+        ["0", "0", "a5", "10", "0"]
+    );
+    a.send(Message::new("5"));
+    assert_eq!(a.recv().unwrap().msg_type(), "5");
+    assert_eq!(a.recv(), None);
+
+    // The order trades while its client is away
+    b.send(order("b3", 2, 2, 4, 100, 3));
+    assert_eq!(
+        fields(&b.recv().unwrap(), &report),
+        ["0", "0", "b3", "4", "0"]
+    );
+    assert_eq!(
+        fields(&b.recv().unwrap(), &report),
+        ["F", "2", "b3", "0", "4"]
+    );
+
+    // The session carries on over a new connection, but only one at a time
+    let mut a = FixClient {
+        seq: a.seq,
+        ..FixClient::connect(&server.addr, "CLIENT_A")
+    };
+    a.logon();
+    let mut twin = FixClient::connect(&server.addr, "CLIENT_A");
+    twin.send(Message::new("A").with(tag::ENCRYPT_METHOD, 0));
+    assert_eq!(twin.recv().unwrap().msg_type(), "5");
+    assert_eq!(twin.recv(), None);
+
+    a.send(
+        Message::new("2")
+            .with(tag::BEGIN_SEQ_NO, 10)
+            .with(tag::END_SEQ_NO, 10),
+    );
+    let missed = a.recv().unwrap();
+    assert_eq!(fields(&missed, &report), ["F", "1", "a5", "6", "4"]);
+    assert_eq!(missed.get(tag::POSS_DUP_FLAG), Some("Y"));
+
+    a.send(
+        Message::new("F")
+            .with(tag::ORIG_CL_ORD_ID, "a5")
+            .with(tag::CL_ORD_ID, "a6"),
+    );
+    let canceled = a.recv().unwrap();
+    assert_eq!(fields(&canceled, &report), ["4", "4", "a6", "0", "4"]);
+    assert_eq!(canceled.get(tag::MSG_SEQ_NUM), Some("12"));
+
+    // Sequence numbers never go back. A reset does not take a number.
+    a.send_numbered(a.seq + 1, Message::new("4").with(tag::NEW_SEQ_NO, 1));
+    let reject = a.recv().unwrap();
+    assert_eq!(reject.msg_type(), "3");
+    assert_eq!(
+        reject.get(tag::REF_SEQ_NUM),
+        Some((a.seq + 1).to_string().as_str())
+    );
+
+    a.send(Message::new("5"));
+    assert_eq!(a.recv().unwrap().msg_type(), "5");
+    assert_eq!(a.recv(), None);
+}
+
+/// Sends one request to the `http` subcommand, returning the status and
+/// the JSON body of the response.
+fn http(addr: &str, method: &str, target: &str, body: &str) -> (u16, serde_json::Value) {
+    use std::io::Read;
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        target,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn test_cli_http_api() {
+    let server = Server::start("http");
+    let addr = &server.addr;
+    let order = |side: &str, price: &str, qty: &str, user_id: u64| {
+        format!(
+            r#"{{"order_type":"Lim","side":"{}","price":"{}","initial_qty":"{}","user_id":{}}}"#,
+            side, price, qty, user_id
+        )
+    };
+    let types = |body: &serde_json::Value| -> Vec<String> {
+        body["reports"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["type"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let (status, body) = http(addr, "POST", "/orders", &order("Sell", "100", "10", 1));
+    assert_eq!(status, 200);
+    assert_eq!(types(&body), ["Accepted", "Queued"]);
+    let resting = body["reports"][0]["order_id"].as_str().unwrap().to_string();
+    http(addr, "POST", "/orders", &order("Sell", "101", "5", 1));
+    let (status, body) = http(addr, "POST", "/orders", &order("Buy", "100", "4", 2));
+    assert_eq!(status, 200);
+    assert_eq!(types(&body), ["Accepted", "Trade", "Executed"]);
+    assert_eq!(body["reports"][1]["price"], "100");
+
+    let (status, body) = http(addr, "GET", "/book/l2", "");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "bids": [],
+            "asks": [
+                {"price": "100", "qty": "6", "orders": 1},
+                {"price": "101", "qty": "5", "orders": 1},
+            ],
+        })
+    );
+    let (_, body) = http(addr, "GET", "/book/l2?depth=1", "");
+    assert_eq!(body["asks"].as_array().unwrap().len(), 1);
+    let (_, body) = http(addr, "GET", "/book/l3", "");
+    assert_eq!(body["asks"][0]["order_id"], resting.as_str());
+    assert_eq!(body["asks"][0]["remaining_qty"], "6");
+    assert_eq!(body["asks"][1]["price"], "101");
+
+    let (_, body) = http(addr, "GET", "/users/1/orders", "");
+    assert_eq!(body["orders"].as_array().unwrap().len(), 2);
+    let (_, body) = http(addr, "GET", "/users/2/orders", "");
+    assert_eq!(body["orders"], serde_json::json!([]));
+
+    let (_, body) = http(addr, "GET", "/trades", "");
+    let trades = body["trades"].as_array().unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["qty"], "4");
+    assert_eq!(trades[0]["maker_order_id"], resting.as_str());
+
+    let cancel = format!("/orders/{}?user_id=1", resting);
+    let (status, body) = http(addr, "DELETE", &cancel, "");
+    assert_eq!(status, 200);
+    assert_eq!(types(&body), ["Canceled"]);
+    let (status, body) = http(addr, "DELETE", &cancel, "");
+    assert_eq!(status, 404);
+    assert!(body["error"].as_str().unwrap().contains(&resting));
+
+    // Rejected orders come with their event
+    let (status, body) = http(addr, "POST", "/orders", &order("Buy", "100", "0", 2));
+    assert_eq!(status, 422);
+    assert_eq!(types(&body), ["Rejected"]);
+    assert_eq!(body["reports"][0]["reason"], "ZeroQuantity");
+
+    let (status, _) = http(addr, "POST", "/orders", r#"{"side":"Buy"}"#);
+    assert_eq!(status, 400);
+    let (status, _) = http(addr, "DELETE", &format!("/orders/{}", resting), "");
+    assert_eq!(status, 400);
+    assert_eq!(http(addr, "PUT", "/trades", "").0, 405);
+    assert_eq!(http(addr, "GET", "/nowhere", "").0, 404);
+}